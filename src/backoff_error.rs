@@ -0,0 +1,34 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+///
+/// The error returned by `TimedCache::try_get_with_backoff` when no value could be retrieved.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffError<E> {
+    ///
+    /// The generator was called and failed with the contained error.
+    ///
+    Failed(E),
+    ///
+    /// The generator was not called, because earlier attempts failed and the key is backing
+    /// off for the contained remaining time.
+    ///
+    BackingOff(Duration),
+}
+
+impl<E: fmt::Display> fmt::Display for BackoffError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BackoffError::Failed(ref error) => write!(f, "failed to generate the value: {}", error),
+            BackoffError::BackingOff(retry_in) => write!(
+                f,
+                "not generating the value after repeated failures, retrying in {:?}",
+                retry_in
+            ),
+        }
+    }
+}
+
+impl<E: Error> Error for BackoffError<E> {}
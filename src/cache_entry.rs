@@ -0,0 +1,140 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::time::Instant;
+use TimedCache;
+
+///
+/// The entry for a single key in a `TimedCache`, returned by `TimedCache::entry`.
+///
+/// Like the entries of a `HashMap`, this allows modifying a value in place if one is stored, or
+/// storing one if not, without looking the key up again. Only a value which `TimedCache::get`
+/// would serve counts as stored: a value which is still valid, or expired but within its
+/// regeneration cooldown. Any other expired value is treated as if there was none.
+///
+pub struct CacheEntry<'a, Key: Hash + Eq, Value, S = RandomState> {
+    cache: &'a mut TimedCache<Key, Value, S>,
+    key: Key,
+}
+
+impl<'a, Key: Hash + Eq, Value, S: BuildHasher> CacheEntry<'a, Key, Value, S> {
+    pub(crate) fn new(
+        cache: &'a mut TimedCache<Key, Value, S>,
+        key: Key,
+    ) -> CacheEntry<'a, Key, Value, S> {
+        CacheEntry { cache, key }
+    }
+
+    ///
+    /// Returns the key of this entry.
+    ///
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    ///
+    /// Calls `f` with the value stored for the key if it counts as stored, then returns the entry
+    /// so that a value can be inserted with `CacheEntry::or_insert_with` if it did not.
+    ///
+    /// This does not change the time the value was stored.
+    ///
+    pub fn and_modify(self, f: impl FnOnce(&mut Value)) -> CacheEntry<'a, Key, Value, S> {
+        if self.cache.servable(&self.key) {
+            let timed_data = self
+                .cache
+                .store
+                .get_mut(&self.key)
+                .expect("key should be present");
+            f(&mut timed_data.item);
+        }
+        self
+    }
+}
+
+///
+/// The methods which record a hit or miss for the key, so need `Key: Clone`.
+///
+impl<'a, Key: Hash + Eq + Clone, Value, S: BuildHasher> CacheEntry<'a, Key, Value, S> {
+    ///
+    /// Returns a mutable reference to the value stored for the key if it counts as stored,
+    /// otherwise stores the value returned by `f` and returns a mutable reference to that,
+    /// like `TimedCache::get`.
+    ///
+    pub fn or_insert_with(self, f: impl FnOnce() -> Value) -> &'a mut Value {
+        let cache = self.cache;
+        if cache.servable(&self.key) {
+            return cache.retrieve_hit(&self.key);
+        }
+        cache.record_miss(&self.key);
+        let started = Instant::now();
+        let value = f();
+        cache.record_generation_time(&self.key, started);
+        let timed_data = cache.new_timed_data(&self.key, value);
+        cache.store_owned(self.key, timed_data)
+    }
+
+    ///
+    /// Behaves like `CacheEntry::or_insert_with`, but stores the `default` if there is no valid
+    /// value stored for the key.
+    ///
+    pub fn or_insert(self, default: Value) -> &'a mut Value {
+        self.or_insert_with(|| default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use {ManualClock, TimedCache};
+
+    #[test]
+    fn should_modify_valid_value_in_place() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+
+        let value = cache
+            .entry(1)
+            .and_modify(|value| *value += 1)
+            .or_insert_with(|| unreachable!());
+        assert_eq!(*value, 11);
+        assert_eq!(cache.get_if_present(&1), Some(&11));
+    }
+
+    #[test]
+    fn should_insert_when_vacant() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        let value = cache
+            .entry(1)
+            .and_modify(|_| unreachable!())
+            .or_insert_with(|| 10);
+        assert_eq!(*value, 10);
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+    }
+
+    #[test]
+    fn should_treat_expired_value_as_vacant() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+        cache.set_entry_ttl(&1, Duration::from_secs(0));
+
+        let value = cache.entry(1).and_modify(|_| unreachable!()).or_insert(20);
+        assert_eq!(*value, 20);
+        assert_eq!(cache.get_if_present(&1), Some(&20));
+    }
+
+    #[test]
+    fn should_treat_expired_value_within_cooldown_as_occupied() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_regeneration_cooldown(Duration::from_secs(20));
+        cache.insert(1, 10);
+        clock.advance(Duration::from_secs(15));
+
+        let value = cache
+            .entry(1)
+            .and_modify(|value| *value += 1)
+            .or_insert_with(|| unreachable!());
+        assert_eq!(*value, 11);
+    }
+}
@@ -0,0 +1,28 @@
+use std::error::Error;
+use std::fmt;
+
+///
+/// The reason a value could not be retrieved from the cache without generating it.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMiss {
+    ///
+    /// A value is stored for the key, but it is no longer considered valid.
+    ///
+    Expired,
+    ///
+    /// No value is stored for the key.
+    ///
+    Absent,
+}
+
+impl fmt::Display for CacheMiss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheMiss::Expired => write!(f, "the cached value has expired"),
+            CacheMiss::Absent => write!(f, "no value is cached for the key"),
+        }
+    }
+}
+
+impl Error for CacheMiss {}
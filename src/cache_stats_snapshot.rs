@@ -0,0 +1,35 @@
+///
+/// The statistics of a `TimedCache` at one moment, returned by `TimedCache::stats_snapshot`,
+/// such as to export to a monitoring system from another thread.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CacheStatsSnapshot {
+    ///
+    /// The hits recorded across every key, as counted for each key by `TimedCache::key_stats`.
+    ///
+    pub hits: u64,
+    ///
+    /// The misses recorded across every key, as counted for each key by `TimedCache::key_stats`.
+    ///
+    pub misses: u64,
+    ///
+    /// The number of times a generator has been called, as returned by
+    /// `TimedCache::generation_count`.
+    ///
+    pub generations: u64,
+    ///
+    /// The number of entries evicted over the cache's lifetime, for every reason described by
+    /// `TimedCache::set_on_evict`.
+    ///
+    pub evictions: u64,
+    ///
+    /// The number of entries stored, including any which are no longer considered valid, as
+    /// returned by `TimedCache::len`.
+    ///
+    pub len: usize,
+    ///
+    /// The number of entries stored which are still considered valid.
+    ///
+    pub valid_len: usize,
+}
@@ -0,0 +1,157 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+///
+/// A source of the current instant, used by a `TimedCache` to decide when values were stored and
+/// whether they are still valid.
+///
+/// A clock should never go backwards, but one which does is tolerated: a value stored at an
+/// instant later than the clock now reads is treated as if it had just been stored. This never
+/// panics, but can make values appear fresh for longer than their time to live.
+///
+pub trait Clock {
+    ///
+    /// Returns the current instant.
+    ///
+    fn now(&self) -> Instant;
+}
+
+///
+/// A `Clock` which reads the system's monotonic clock. This is the clock a `TimedCache` uses by
+/// default.
+///
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+///
+/// A `Clock` which only moves when it is advanced, for testing code that uses a `TimedCache`
+/// without waiting for values to expire.
+///
+/// Clones of a `ManualClock` share the same time, so a test can give one clone to a cache with
+/// `TimedCache::set_clock` and keep another to advance it later.
+///
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    ///
+    /// Creates a `ManualClock` that starts at the current instant.
+    ///
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    ///
+    /// Moves the clock, and every clone of it, forward by `duration`, or to the latest instant
+    /// which can be represented if that is sooner, like an offset clock.
+    ///
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(PoisonError::into_inner);
+        *now = saturating_add(*now, duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+///
+/// A `Clock` which reads another clock shifted forward by a fixed offset.
+///
+#[cfg(any(test, feature = "testing"))]
+pub(crate) struct OffsetClock {
+    pub(crate) clock: Box<dyn Clock + Send>,
+    pub(crate) offset: Duration,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for OffsetClock {
+    fn now(&self) -> Instant {
+        saturating_add(self.clock.now(), self.offset)
+    }
+}
+
+///
+/// Returns the `instant` moved forward by the `duration`, or the latest instant which can be
+/// represented if that would overflow.
+///
+pub(crate) fn saturating_add(instant: Instant, duration: Duration) -> Instant {
+    if let Some(later) = instant.checked_add(duration) {
+        return later;
+    }
+    let mut latest = instant;
+    let mut step = duration;
+    while step > Duration::from_secs(0) {
+        match latest.checked_add(step) {
+            Some(later) => latest = later,
+            None => step /= 2,
+        }
+    }
+    latest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{saturating_add, Clock, ManualClock, OffsetClock};
+    use std::time::Duration;
+
+    #[test]
+    fn should_advance_every_clone_of_manual_clock() {
+        let clock = ManualClock::new();
+        let clone = clock.clone();
+        let start = clock.now();
+
+        clone.advance(Duration::from_secs(5));
+
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+        assert_eq!(clone.now(), clock.now());
+    }
+
+    #[test]
+    fn should_saturate_manual_clock_instead_of_overflowing() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        clock.advance(Duration::MAX);
+        clock.advance(Duration::from_secs(1));
+
+        assert!(clock.now() > start);
+        assert_eq!(clock.now().checked_add(Duration::from_nanos(1)), None);
+    }
+
+    #[test]
+    fn should_saturate_offset_clock_instead_of_overflowing() {
+        let clock = ManualClock::new();
+        let offset_clock = OffsetClock {
+            clock: Box::new(clock.clone()),
+            offset: Duration::MAX,
+        };
+
+        let latest = offset_clock.now();
+
+        assert!(latest > clock.now());
+        assert_eq!(latest.checked_add(Duration::from_nanos(1)), None);
+        assert_eq!(
+            saturating_add(clock.now(), Duration::from_secs(5)),
+            clock.now() + Duration::from_secs(5)
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+///
+/// A cache which stores each entry's expiry as a `u32` count of whole seconds since the cache
+/// was created, rather than a full `Instant` and time to live, to save memory when storing a
+/// great many small values.
+///
+/// The saving comes at the cost of resolution: the instant each entry expires is rounded down to
+/// the second, so an entry may expire up to a second before its time to live has fully elapsed,
+/// but never after. An entry with a time to live of less than a second may therefore never be
+/// valid at all. An entry cannot expire later than `u32::MAX` seconds (about 136 years) after the
+/// cache was created.
+///
+/// Time is checked against the system's monotonic clock.
+///
+pub struct CompactTimedCache<Key: Hash + Eq, Value, S = RandomState> {
+    time_to_keep: Duration,
+    ///
+    /// The instant every entry's expiry is counted from.
+    ///
+    epoch: Instant,
+    ///
+    /// Each value with the second after the epoch from which it is no longer valid.
+    ///
+    store: HashMap<Key, (Value, u32), S>,
+}
+
+impl<Key: Hash + Eq, Value> CompactTimedCache<Key, Value> {
+    ///
+    /// Creates a `CompactTimedCache` with the specified `Duration` as the length of time values
+    /// will be considered valid after they are inserted.
+    ///
+    pub fn with_time_to_keep(time_to_keep: Duration) -> CompactTimedCache<Key, Value> {
+        CompactTimedCache::new(time_to_keep, Instant::now(), HashMap::new())
+    }
+}
+
+impl<Key: Hash + Eq, Value, S: BuildHasher> CompactTimedCache<Key, Value, S> {
+    pub(crate) fn new(
+        time_to_keep: Duration,
+        epoch: Instant,
+        store: HashMap<Key, (Value, u32), S>,
+    ) -> CompactTimedCache<Key, Value, S> {
+        CompactTimedCache {
+            time_to_keep,
+            epoch,
+            store,
+        }
+    }
+
+    ///
+    /// Inserts the `value` for the `key`, returning the value previously stored for it, if any,
+    /// whether or not it was still valid.
+    ///
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        self.insert_at(key, value, Instant::now())
+    }
+
+    pub(crate) fn insert_at(&mut self, key: Key, value: Value, now: Instant) -> Option<Value> {
+        let expires_at = expiry_tick(self.epoch, now, self.time_to_keep);
+        self.store
+            .insert(key, (value, expires_at))
+            .map(|(value, _)| value)
+    }
+
+    ///
+    /// Retrieves a reference to the value stored for the `key` if it exists and is still
+    /// considered valid, like `TimedCache::get_if_present`.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<&Value> {
+        self.get_if_present_at(key, Instant::now())
+    }
+
+    pub(crate) fn get_if_present_at(&self, key: &Key, now: Instant) -> Option<&Value> {
+        let now = tick(self.epoch, now);
+        self.store
+            .get(key)
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(value, _)| value)
+    }
+
+    ///
+    /// Checks whether a valid value is stored for the `key`.
+    ///
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.get_if_present(key).is_some()
+    }
+
+    ///
+    /// Removes the value stored for the `key`, returning it if it was still valid.
+    ///
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let now = tick(self.epoch, Instant::now());
+        self.store
+            .remove(key)
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(value, _)| value)
+    }
+
+    ///
+    /// Removes every entry which is no longer considered valid.
+    ///
+    pub fn purge_expired(&mut self) {
+        let now = tick(self.epoch, Instant::now());
+        self.store.retain(|_, (_, expires_at)| now < *expires_at);
+    }
+
+    ///
+    /// Returns the number of entries stored, including any which are no longer considered
+    /// valid, like `TimedCache::len`.
+    ///
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    ///
+    /// Returns `true` if no entries are stored.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+}
+
+///
+/// Returns the number of whole seconds from the `epoch` to `now`, saturating at `u32::MAX`.
+///
+fn tick(epoch: Instant, now: Instant) -> u32 {
+    let seconds = now.saturating_duration_since(epoch).as_secs();
+    seconds.min(u64::from(u32::MAX)) as u32
+}
+
+///
+/// Returns the tick from which a value stored at `now` with the `time_to_live` is no longer
+/// valid, which is the instant it expires rounded down to whole seconds.
+///
+pub(crate) fn expiry_tick(epoch: Instant, now: Instant, time_to_live: Duration) -> u32 {
+    let expires_after = now
+        .saturating_duration_since(epoch)
+        .saturating_add(time_to_live);
+    expires_after.as_secs().min(u64::from(u32::MAX)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompactTimedCache;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use {EvictionReason, ManualClock, TimedCache};
+
+    #[test]
+    fn should_expire_at_second_granularity() {
+        let mut cache = CompactTimedCache::with_time_to_keep(Duration::from_secs(10));
+        let start = cache.epoch;
+        cache.insert_at(1, 10, start);
+        cache.insert_at(2, 20, start + Duration::from_millis(1500));
+
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        assert_eq!(cache.get_if_present_at(&1, at(9)), Some(&10));
+        assert_eq!(cache.get_if_present_at(&1, at(10)), None);
+        assert_eq!(cache.get_if_present_at(&2, at(10)), Some(&20));
+        assert_eq!(
+            cache.get_if_present_at(&2, at(10) + Duration::from_millis(999)),
+            Some(&20)
+        );
+        assert_eq!(cache.get_if_present_at(&2, at(11)), None);
+        assert_eq!(cache.get_if_present_at(&3, at(0)), None);
+    }
+
+    #[test]
+    fn should_expire_less_than_a_second_early_and_never_late() {
+        let mut cache = CompactTimedCache::with_time_to_keep(Duration::from_secs(10));
+        let start = cache.epoch;
+        let stored = start + Duration::from_millis(999);
+        cache.insert_at(1, 10, stored);
+
+        let expires = stored + Duration::from_secs(10);
+        assert_eq!(
+            cache.get_if_present_at(&1, expires - Duration::from_millis(1000)),
+            Some(&10)
+        );
+        assert_eq!(
+            cache.get_if_present_at(&1, expires - Duration::from_millis(999)),
+            None
+        );
+        assert_eq!(cache.get_if_present_at(&1, expires), None);
+    }
+
+    #[test]
+    fn should_never_keep_value_expiring_in_the_second_it_was_stored() {
+        let mut cache = CompactTimedCache::with_time_to_keep(Duration::from_millis(999));
+        let start = cache.epoch;
+        cache.insert_at(1, 10, start);
+
+        assert_eq!(cache.get_if_present_at(&1, start), None);
+    }
+
+    #[test]
+    fn should_keep_only_valid_entries_when_compressing_timestamps() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.set_entry_ttl(&2, Duration::from_secs(0));
+
+        let compact = cache.compress_timestamps();
+
+        assert_eq!(compact.len(), 1);
+        assert_eq!(compact.get_if_present(&1), Some(&10));
+        assert!(!compact.contains_key(&2));
+    }
+
+    #[test]
+    fn should_report_entries_dropped_when_compressing_timestamps_as_expired() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = evictions.clone();
+        cache.set_on_evict_with_reason(move |key, value, reason| {
+            recorded.lock().unwrap().push((*key, *value, reason))
+        });
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.set_entry_ttl(&2, Duration::from_secs(0));
+
+        let compact = cache.compress_timestamps();
+
+        assert_eq!(compact.len(), 1);
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(2, 20, EvictionReason::Expired)]
+        );
+    }
+
+    #[test]
+    fn should_measure_time_left_by_cache_clock_when_compressing_timestamps() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 10);
+        clock.advance(Duration::from_secs(5));
+        cache.insert(2, 20);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.is_expired(&1), Some(true));
+
+        let compact = cache.compress_timestamps();
+
+        assert!(!compact.contains_key(&1));
+        assert_eq!(compact.get_if_present(&2), Some(&20));
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+///
+/// The time to keep used by the `Default` implementations until the application sets its own
+/// with `set_global_default_ttl`.
+///
+const BUILT_IN_DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+///
+/// Marks that no global default time to keep has been set.
+///
+const UNSET: u64 = u64::MAX;
+
+///
+/// The global default time to keep in nanoseconds, or `UNSET`.
+///
+static GLOBAL_DEFAULT_TTL_NANOS: AtomicU64 = AtomicU64::new(UNSET);
+
+///
+/// Sets the time to keep given to caches created with `Default::default`, such as
+/// `TimedCache::default()` and `TimedMemo::default()`, in place of the built-in default of 60
+/// seconds.
+///
+/// This only affects caches created afterwards, and can be called from any thread. A `ttl`
+/// longer than about 584 years is cut down to that.
+///
+pub fn set_global_default_ttl(ttl: Duration) {
+    let nanos = ttl.as_nanos().min(u128::from(UNSET - 1)) as u64;
+    GLOBAL_DEFAULT_TTL_NANOS.store(nanos, Ordering::Relaxed);
+}
+
+///
+/// Returns the time to keep set with `set_global_default_ttl`, or the built-in default if none
+/// has been set.
+///
+pub(crate) fn global_default_ttl() -> Duration {
+    match GLOBAL_DEFAULT_TTL_NANOS.load(Ordering::Relaxed) {
+        UNSET => BUILT_IN_DEFAULT_TTL,
+        nanos => Duration::from_nanos(nanos),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TimedCache;
+    use TimedMemo;
+
+    // NOTE: This is the only test which sets the global default, as tests run in parallel.
+    #[test]
+    fn should_create_default_caches_with_global_default_ttl() {
+        assert_eq!(global_default_ttl(), BUILT_IN_DEFAULT_TTL);
+
+        set_global_default_ttl(Duration::from_secs(5));
+
+        let cache = TimedCache::<usize, usize>::default();
+        assert_eq!(cache.time_to_keep, Duration::from_secs(5));
+        let memo = TimedMemo::<usize>::default();
+        assert_eq!(memo.time_to_keep, Duration::from_secs(5));
+    }
+}
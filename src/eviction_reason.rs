@@ -0,0 +1,30 @@
+///
+/// Why an entry was evicted from a `TimedCache`, as passed to the callback set with
+/// `TimedCache::set_on_evict_with_reason`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EvictionReason {
+    ///
+    /// The value was replaced by a new value stored for the same key.
+    ///
+    Overwritten,
+    ///
+    /// The entry was removed explicitly, such as by `TimedCache::remove`, or because it did not
+    /// match the predicate given to `TimedCache::retain_valid`.
+    ///
+    Removed,
+    ///
+    /// The entry was no longer considered valid.
+    ///
+    Expired,
+    ///
+    /// The entry was the least recently used when the cache needed room to stay within its
+    /// capacity.
+    ///
+    Capacity,
+    ///
+    /// The entry was still stored when the cache was dropped.
+    ///
+    Dropped,
+}
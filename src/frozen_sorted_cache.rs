@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+use timed_data::TimedData;
+
+///
+/// A `TimedCache` which can no longer be modified, with its entries sorted by key, returned by
+/// `TimedCache::into_sorted_vec`.
+///
+/// Like `FrozenTimedCache`, it can only look up values which are already stored and still
+/// valid, but its entries are kept in one sorted `Vec` and found by binary search rather than
+/// hashing. This takes less memory than a `HashMap` and keeps neighbouring keys together, which
+/// suits read-mostly lookups over a great many keys.
+///
+/// Values still expire on schedule, checked against the system's monotonic clock, as a frozen
+/// cache does not keep any `Clock` set on the cache it was frozen from. Each value keeps the time
+/// it had left by that clock when the cache was frozen.
+///
+pub struct FrozenSortedCache<Key: Ord, Value> {
+    time_to_keep: Duration,
+    entries: Vec<(Key, TimedData<Value>)>,
+}
+
+impl<Key: Ord, Value> FrozenSortedCache<Key, Value> {
+    pub(crate) fn new(
+        time_to_keep: Duration,
+        mut entries: Vec<(Key, TimedData<Value>)>,
+    ) -> FrozenSortedCache<Key, Value> {
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        FrozenSortedCache {
+            time_to_keep,
+            entries,
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored for the `key` if it exists and is still
+    /// considered valid, like `TimedCache::get_if_present`.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<&Value> {
+        let now = Instant::now();
+        self.entries
+            .binary_search_by(|(stored, _)| stored.cmp(key))
+            .ok()
+            .map(|index| &self.entries[index].1)
+            .filter(|timed_data| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|timed_data| &timed_data.item)
+    }
+
+    ///
+    /// Checks whether a valid value is stored for the `key`.
+    ///
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.get_if_present(key).is_some()
+    }
+
+    ///
+    /// Returns the number of entries stored, including any which are no longer considered
+    /// valid, like `TimedCache::len`.
+    ///
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    ///
+    /// Returns `true` if no entries are stored.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    ///
+    /// Iterates over every entry that is still considered valid, in order of key.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> + '_ {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(move |(_, timed_data)| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|(key, timed_data)| (key, &timed_data.item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use {ManualClock, TimedCache};
+
+    #[test]
+    fn should_look_up_keys_in_sorted_cache() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        for key in &[5, 1, 9, 3] {
+            cache.insert(*key, key * 10);
+        }
+        cache.set_entry_ttl(&9, Duration::from_secs(0));
+        let sorted = cache.into_sorted_vec();
+
+        assert_eq!(sorted.get_if_present(&1), Some(&10));
+        assert_eq!(sorted.get_if_present(&5), Some(&50));
+        assert_eq!(sorted.get_if_present(&9), None);
+        assert!(!sorted.contains_key(&4));
+        assert_eq!(
+            sorted.iter().collect::<Vec<_>>(),
+            vec![(&1, &10), (&3, &30), (&5, &50)]
+        );
+        assert_eq!(sorted.len(), 4);
+    }
+
+    #[test]
+    fn should_keep_time_left_by_cache_clock_when_sorted() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 10);
+        clock.advance(Duration::from_secs(5));
+        cache.insert(2, 20);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.is_expired(&1), Some(true));
+
+        let sorted = cache.into_sorted_vec();
+
+        assert_eq!(sorted.get_if_present(&1), None);
+        assert_eq!(sorted.get_if_present(&2), Some(&20));
+    }
+}
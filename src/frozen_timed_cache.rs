@@ -0,0 +1,129 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+use timed_data::TimedData;
+
+///
+/// A `TimedCache` which can no longer be modified, returned by `TimedCache::freeze`.
+///
+/// A frozen cache can only look up values which are already stored and still valid. As nothing
+/// can change it, it is `Sync` whenever its keys and values are, so it can be shared between
+/// threads in an `Arc` without a lock.
+///
+/// Values still expire on schedule, checked against the system's monotonic clock, as a frozen
+/// cache does not keep any `Clock` set on the cache it was frozen from. Each value keeps the time
+/// it had left by that clock when the cache was frozen.
+///
+pub struct FrozenTimedCache<Key: Hash + Eq, Value, S = RandomState> {
+    time_to_keep: Duration,
+    store: HashMap<Key, TimedData<Value>, S>,
+}
+
+impl<Key: Hash + Eq, Value, S: BuildHasher> FrozenTimedCache<Key, Value, S> {
+    pub(crate) fn new(
+        time_to_keep: Duration,
+        store: HashMap<Key, TimedData<Value>, S>,
+    ) -> FrozenTimedCache<Key, Value, S> {
+        FrozenTimedCache {
+            time_to_keep,
+            store,
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored for the `key` if it exists and is still
+    /// considered valid, like `TimedCache::get_if_present`.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<&Value> {
+        let now = Instant::now();
+        self.store
+            .get(key)
+            .filter(|timed_data| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|timed_data| &timed_data.item)
+    }
+
+    ///
+    /// Checks whether a valid value is stored for the `key`.
+    ///
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.get_if_present(key).is_some()
+    }
+
+    ///
+    /// Returns the number of entries stored, including any which are no longer considered
+    /// valid, like `TimedCache::len`.
+    ///
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    ///
+    /// Returns `true` if no entries are stored.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    ///
+    /// Iterates over every entry that is still considered valid, in an arbitrary order.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> + '_ {
+        let now = Instant::now();
+        self.store
+            .iter()
+            .filter(move |(_, timed_data)| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|(key, timed_data)| (key, &timed_data.item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use {ManualClock, TimedCache};
+
+    #[test]
+    fn should_read_frozen_cache_from_many_threads() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.set_entry_ttl(&2, Duration::from_secs(0));
+        let frozen = Arc::new(cache.freeze());
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = frozen.clone();
+                thread::spawn(move || {
+                    assert_eq!(frozen.get_if_present(&1), Some(&10));
+                    assert!(!frozen.contains_key(&2));
+                    frozen.iter().count()
+                })
+            })
+            .collect();
+
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 1);
+        }
+        assert_eq!(frozen.len(), 2);
+        assert!(!frozen.is_empty());
+    }
+
+    #[test]
+    fn should_keep_time_left_by_cache_clock_when_frozen() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 10);
+        clock.advance(Duration::from_secs(5));
+        cache.insert(2, 20);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.is_expired(&1), Some(true));
+
+        let frozen = cache.freeze();
+
+        assert_eq!(frozen.get_if_present(&1), None);
+        assert_eq!(frozen.get_if_present(&2), Some(&20));
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+///
+/// A seeded source of random extra time to add to an entry's time to live, so that entries
+/// stored at the same time do not all expire at the same time.
+///
+pub(crate) struct Jitter {
+    ///
+    /// The exclusive upper bound on the extra time added.
+    ///
+    max_jitter: Duration,
+    rng: SplitMix64,
+}
+
+impl Jitter {
+    pub(crate) fn new(max_jitter: Duration, seed: u64) -> Jitter {
+        Jitter {
+            max_jitter,
+            rng: SplitMix64::new(seed),
+        }
+    }
+
+    ///
+    /// Returns `time_to_live` plus a random amount of time less than the maximum jitter.
+    ///
+    pub(crate) fn next_time_to_live(&mut self, time_to_live: Duration) -> Duration {
+        let max_nanos = self.max_jitter.as_nanos().min(u128::from(u64::MAX));
+        let jitter_nanos = (u128::from(self.rng.next_u64()) * max_nanos) >> 64;
+        time_to_live.saturating_add(Duration::from_nanos(jitter_nanos as u64))
+    }
+}
+
+///
+/// A seeded random number generator using SplitMix64, which is plenty for spreading out expiry
+/// and other non-cryptographic choices.
+///
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    ///
+    /// Returns a number between `0.0` (inclusive) and `1.0` (exclusive).
+    ///
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_add_jitter_less_than_maximum() {
+        let time_to_live = Duration::from_secs(10);
+        let max_jitter = Duration::from_secs(2);
+        let mut jitter = Jitter::new(max_jitter, 42);
+
+        (0..1000).for_each(|_| {
+            let jittered = jitter.next_time_to_live(time_to_live);
+            assert!(jittered >= time_to_live);
+            assert!(jittered < time_to_live + max_jitter);
+        });
+    }
+
+    #[test]
+    fn should_produce_same_jitter_for_same_seed() {
+        let time_to_live = Duration::from_secs(10);
+        let mut a = Jitter::new(Duration::from_secs(2), 7);
+        let mut b = Jitter::new(Duration::from_secs(2), 7);
+
+        (0..100).for_each(|_| {
+            assert_eq!(
+                a.next_time_to_live(time_to_live),
+                b.next_time_to_live(time_to_live)
+            );
+        });
+    }
+
+    #[test]
+    fn should_not_add_jitter_when_maximum_is_zero() {
+        let time_to_live = Duration::from_secs(10);
+        let mut jitter = Jitter::new(Duration::from_secs(0), 1);
+
+        assert_eq!(jitter.next_time_to_live(time_to_live), time_to_live);
+    }
+}
@@ -0,0 +1,122 @@
+use clock::saturating_add;
+use std::time::{Duration, Instant};
+
+///
+/// Counts of how often the value for a key was found in the cache, and how often it had to be
+/// generated.
+///
+/// These describe the key rather than any one stored value, so they are kept separately from the
+/// `TimedData` and survive regeneration of the value.
+///
+/// Each count saturates at `u64::MAX` rather than overflowing, so a key hot enough to reach it
+/// over a very long uptime simply stops being counted.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KeyStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    ///
+    /// How long the generator took the last time the value for the key was generated after a
+    /// miss, if it ever has been.
+    ///
+    pub(crate) last_generation_time: Option<Duration>,
+    ///
+    /// How many times in a row generating the value for the key has failed since it last
+    /// succeeded.
+    ///
+    pub(crate) consecutive_failures: u32,
+    ///
+    /// The instant before which generating the value for the key should not be attempted again,
+    /// if it is backing off after a failure.
+    ///
+    pub(crate) backoff_until: Option<Instant>,
+}
+
+impl KeyStats {
+    pub(crate) fn record_hit(&mut self) {
+        self.hits = self.hits.saturating_add(1);
+    }
+
+    pub(crate) fn record_miss(&mut self) {
+        self.misses = self.misses.saturating_add(1);
+    }
+
+    ///
+    /// Records a failed generation at `now`, backing off for `initial` after the first failure in
+    /// a row and doubling for each failure after it, up to `max`. A backoff reaching past the
+    /// latest instant which can be represented ends at that instant instead.
+    ///
+    pub(crate) fn record_failure(&mut self, now: Instant, initial: Duration, max: Duration) {
+        let doublings = self.consecutive_failures.min(31);
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let backoff = initial.saturating_mul(1 << doublings).min(max);
+        self.backoff_until = Some(saturating_add(now, backoff));
+    }
+
+    pub(crate) fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.backoff_until = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_hits_and_misses_separately() {
+        let mut stats = KeyStats::default();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn should_saturate_counts_at_max() {
+        let mut stats = KeyStats {
+            hits: u64::MAX - 1,
+            misses: u64::MAX,
+            last_generation_time: None,
+            consecutive_failures: 0,
+            backoff_until: None,
+        };
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        assert_eq!(stats.hits, u64::MAX);
+        assert_eq!(stats.misses, u64::MAX);
+    }
+
+    #[test]
+    fn should_double_backoff_for_each_failure_up_to_max() {
+        let mut stats = KeyStats::default();
+        let now = Instant::now();
+        let initial = Duration::from_secs(1);
+        let max = Duration::from_secs(5);
+
+        for expected in &[1, 2, 4, 5, 5] {
+            stats.record_failure(now, initial, max);
+            assert_eq!(
+                stats.backoff_until,
+                Some(now + Duration::from_secs(*expected))
+            );
+        }
+        stats.record_success();
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(stats.backoff_until, None);
+    }
+
+    #[test]
+    fn should_saturate_backoff_instead_of_overflowing() {
+        let mut stats = KeyStats::default();
+        let now = Instant::now();
+
+        stats.record_failure(now, Duration::MAX, Duration::MAX);
+
+        let backoff_until = stats.backoff_until.expect("should be backing off");
+        assert!(backoff_until > now);
+        assert_eq!(backoff_until.checked_add(Duration::from_nanos(1)), None);
+    }
+}
@@ -37,13 +37,119 @@
 //!    });
 //! ```
 //!
+mod backoff_error;
+mod cache_entry;
+mod cache_miss;
+mod cache_stats_snapshot;
+mod clock;
+mod compact_timed_cache;
+mod default_ttl;
+mod eviction_reason;
+mod frozen_sorted_cache;
+mod frozen_timed_cache;
+mod jitter;
+mod key_stats;
+mod local_timed_cache;
+mod read_view;
+mod reentrant_generation;
+mod sync_timed_cache;
 mod timed_data;
+mod timed_memo;
+mod timed_set;
+mod timeout_error;
+mod transformed_timed_cache;
+mod value_guard;
 
+pub use backoff_error::BackoffError;
+pub use cache_entry::CacheEntry;
+pub use cache_miss::CacheMiss;
+pub use cache_stats_snapshot::CacheStatsSnapshot;
+use clock::saturating_add;
+#[cfg(any(test, feature = "testing"))]
+use clock::OffsetClock;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use compact_timed_cache::CompactTimedCache;
+pub use default_ttl::set_global_default_ttl;
+pub use eviction_reason::EvictionReason;
+pub use frozen_sorted_cache::FrozenSortedCache;
+pub use frozen_timed_cache::FrozenTimedCache;
+pub use local_timed_cache::LocalTimedCache;
+pub use read_view::ReadView;
+pub use reentrant_generation::ReentrantGeneration;
+pub use sync_timed_cache::{GetOutcome, SyncTimedCache};
+pub use timed_memo::TimedMemo;
+pub use timed_set::TimedSet;
+pub use timeout_error::TimeoutError;
+pub use transformed_timed_cache::TransformedTimedCache;
+pub use value_guard::ValueGuard;
+
+use compact_timed_cache::expiry_tick;
+use jitter::{Jitter, SplitMix64};
+use key_stats::KeyStats;
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::hash::Hash;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
+use std::ops::RangeBounds;
+use std::panic::{self, UnwindSafe};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use timed_data::TimedData;
 
+///
+/// A boxed generator function that produces a value for a given key.
+///
+type Generator<Key, Value> = Box<dyn Fn(&Key) -> Value + Send>;
+
+///
+/// A boxed callback invoked with each entry evicted from the cache, and why it was evicted.
+///
+type EvictionCallback<Key, Value> = Box<dyn FnMut(&Key, &Value, EvictionReason) + Send>;
+
+///
+/// The callback invoked for entries evicted from the cache, if one has been set, along with a
+/// count of every eviction.
+///
+struct EvictionNotifier<Key, Value> {
+    callback: Option<EvictionCallback<Key, Value>>,
+    ///
+    /// The number of entries evicted, saturating at `u64::MAX`.
+    ///
+    count: u64,
+}
+
+///
+/// A boxed observer invoked with the key of each hit or miss.
+///
+type KeyObserver<Key> = Box<dyn Fn(&Key) + Send>;
+
+///
+/// A boxed callback invoked with the number of entries stored and the capacity, whenever the
+/// cache is full.
+///
+type PressureCallback = Box<dyn Fn(usize, usize) + Send>;
+
+///
+/// A boxed predicate deciding something about a key.
+///
+type KeyPredicate<Key> = Box<dyn Fn(&Key) -> bool + Send>;
+
+///
+/// A boxed predicate deciding something about a value.
+///
+type ValuePredicate<Value> = Box<dyn Fn(&Value) -> bool + Send>;
+
+///
+/// A function comparing two values for equality.
+///
+type ValueEq<Value> = fn(&Value, &Value) -> bool;
+
 ///
 /// A collection which stores a value for a set amount of time.
 ///
@@ -51,7 +157,15 @@ use timed_data::TimedData;
 /// valid for the specified length of time, it will be re-generated using the generator function
 /// provided when attempting to retrieve a value using `TimedCache::get`.
 ///
-pub struct TimedCache<Key: Hash + Eq + Clone, Value> {
+/// Like `HashMap`, the cache hashes keys using `RandomState` by default, but a different hasher
+/// can be supplied using `TimedCache::with_hasher`.
+///
+/// Keys need not be `Clone`, as long as only the methods which never clone a key are used, such
+/// as `TimedCache::insert`, `TimedCache::get_if_present` and `TimedCache::remove`. The methods
+/// which copy keys need `Key: Clone`, such as `TimedCache::get`, which copies the key it is given
+/// in order to store a value for it.
+///
+pub struct TimedCache<Key: Hash + Eq, Value, S = RandomState> {
     ///
     /// The amount of time a value will be considered 'valid'.
     ///
@@ -59,106 +173,6205 @@ pub struct TimedCache<Key: Hash + Eq + Clone, Value> {
     ///
     /// The place this storage will be held.
     ///
-    store: HashMap<Key, TimedData<Value>>,
+    store: HashMap<Key, TimedData<Value>, S>,
+    ///
+    /// The hit and miss counts for each key that has been retrieved.
+    ///
+    key_stats: HashMap<Key, KeyStats, S>,
+    ///
+    /// The values initialized once by `TimedCache::get_or_init`, which never expire.
+    ///
+    initialized: HashMap<Key, Value, S>,
+    ///
+    /// The generator used by `TimedCache::get_or_compute`, if one has been set.
+    ///
+    on_miss: Option<Generator<Key, Value>>,
+    ///
+    /// The callback invoked for entries evicted from the cache, if one has been set, and the
+    /// number of entries evicted.
+    ///
+    on_evict: EvictionNotifier<Key, Value>,
+    ///
+    /// The callback invoked when storing a value evicts an entry to stay within capacity, if one
+    /// has been set.
+    ///
+    on_pressure: Option<PressureCallback>,
+    ///
+    /// Whether retrieving a valid value resets the time it was stored.
+    ///
+    sliding: bool,
+    ///
+    /// The source of extra time to live given to each stored value, if any.
+    ///
+    jitter: Option<Jitter>,
+    ///
+    /// The maximum number of entries the cache will hold, if bounded.
+    ///
+    capacity: Option<usize>,
+    ///
+    /// The number of times values have been stored or retrieved, used to order entries by how
+    /// recently they were used.
+    ///
+    uses: u64,
+    ///
+    /// How long after a value is stored it will be served by `TimedCache::get` even once
+    /// expired, if set.
+    ///
+    regeneration_cooldown: Option<Duration>,
+    ///
+    /// The initial and maximum backoff after a failed generation by
+    /// `TimedCache::try_get_with_backoff`, if set.
+    ///
+    generation_backoff: Option<(Duration, Duration)>,
+    ///
+    /// The source of the current instant.
+    ///
+    clock: Box<dyn Clock + Send>,
+    ///
+    /// The observer invoked for each hit, if one has been set.
+    ///
+    hit_observer: Option<KeyObserver<Key>>,
+    ///
+    /// The observer invoked for each miss, if one has been set.
+    ///
+    miss_observer: Option<KeyObserver<Key>>,
+    ///
+    /// The most recent samples of the number of entries, and how many to keep, if sampling has
+    /// been enabled.
+    ///
+    size_samples: Option<(VecDeque<(Instant, usize)>, usize)>,
+    ///
+    /// The shortest time to live any value can be given, if set.
+    ///
+    min_ttl: Option<Duration>,
+    ///
+    /// The number of times to live which have been raised to the minimum time to live.
+    ///
+    clamped_ttls: u64,
+    ///
+    /// The ratio of expired entries above which they are purged automatically, and how many
+    /// values have been stored since the ratio was last checked, if enabled.
+    ///
+    auto_purge: Option<(f32, u32)>,
+    ///
+    /// The number of times a generator has been called by the cache.
+    ///
+    generations: u64,
+    ///
+    /// Whether `TimedCache::smart_get` falls back to an expired value when its generator fails.
+    ///
+    serve_stale_on_error: bool,
+    ///
+    /// The predicate matching keys whose values must never be stored, if one has been set.
+    ///
+    uncacheable: Option<KeyPredicate<Key>>,
+    ///
+    /// The rules giving matching keys their own time to live, in the order they were added.
+    ///
+    ttl_rules: Vec<(KeyPredicate<Key>, Duration)>,
+    ///
+    /// The last value generated for an uncacheable key, held only so that a reference to it can
+    /// be returned.
+    ///
+    uncached: Option<Value>,
+    ///
+    /// The most recent misses with the instants they happened, oldest first, and how many to
+    /// keep, if the miss log has been enabled.
+    ///
+    miss_log: Option<(VecDeque<(Instant, Key)>, usize)>,
+    ///
+    /// The longest time to live a value which stays the same across regenerations can grow to,
+    /// and how to compare the values, if adaptive times to live have been enabled.
+    ///
+    adaptive_ttl: Option<(Duration, ValueEq<Value>)>,
+    ///
+    /// How to compare regenerated values with the ones they replace, how many regenerations
+    /// have replaced a value, and how many of those left it unchanged, if tracking has been
+    /// enabled.
+    ///
+    regeneration_tracking: Option<(ValueEq<Value>, u64, u64)>,
+    ///
+    /// The check a generated value must pass to replace the value already stored, if one has
+    /// been set.
+    ///
+    validator: Option<ValuePredicate<Value>>,
+    ///
+    /// The number of generated values which have failed the validator.
+    ///
+    validation_failures: u64,
+    ///
+    /// How long the most recent generations took, oldest first.
+    ///
+    generation_latencies: VecDeque<Duration>,
 }
 
-impl<Key: Hash + Eq + Clone, Value> TimedCache<Key, Value> {
+///
+/// How many values are stored between each check of whether to purge expired entries
+/// automatically.
+///
+const AUTO_PURGE_CHECK_INTERVAL: u32 = 64;
+
+///
+/// How many of the most recent generation times are kept to estimate latency percentiles from.
+///
+const GENERATION_LATENCY_SAMPLES: usize = 1024;
+
+impl<Key: Hash + Eq, Value> TimedCache<Key, Value> {
     ///
     /// Creates a `TimedCache` with the specified `Duration` as the length of time the values will
     /// be considered 'valid' after initial storage.
     ///
+    /// This does not allocate: memory for entries is only allocated when the first value is
+    /// stored, so an empty cache is cheap to create (for example, in a `lazy_static`).
+    ///
     pub fn with_time_to_keep(time_to_keep: Duration) -> TimedCache<Key, Value> {
+        TimedCache::with_hasher(time_to_keep, RandomState::new())
+    }
+
+    ///
+    /// Creates a `TimedCache` where values will be considered 'valid' for `secs` seconds after
+    /// initial storage.
+    ///
+    pub fn with_ttl_secs(secs: u64) -> TimedCache<Key, Value> {
+        TimedCache::with_time_to_keep(Duration::from_secs(secs))
+    }
+
+    ///
+    /// Creates a `TimedCache` where values will be considered 'valid' for `millis` milliseconds
+    /// after initial storage.
+    ///
+    pub fn with_ttl_millis(millis: u64) -> TimedCache<Key, Value> {
+        TimedCache::with_time_to_keep(Duration::from_millis(millis))
+    }
+
+    ///
+    /// Creates a `TimedCache` with the specified `Duration` as its time to keep, holding the
+    /// `entries` returned by `TimedCache::to_vec`.
+    ///
+    /// Each entry is treated as freshly stored, with its remaining time to live as its own time
+    /// to live, so it expires when it would have in the original cache (give or take the time
+    /// spent in between).
+    ///
+    pub fn from_vec(
+        time_to_keep: Duration,
+        entries: impl IntoIterator<Item = (Key, Value, Duration)>,
+    ) -> TimedCache<Key, Value> {
+        let mut cache = TimedCache::with_time_to_keep(time_to_keep);
+        for (key, value, remaining) in entries {
+            let mut timed_data = cache.new_timed_data(&key, value);
+            timed_data.time_to_live = Some(remaining);
+            let _ = cache.insert_timed_data(key, timed_data);
+        }
+        cache
+    }
+
+    ///
+    /// Creates a `TimedCache` with a sliding window of validity, where each value is given a
+    /// time to live of `time_to_keep` plus a random amount of extra time less than `max_jitter`.
+    ///
+    /// The extra time is chosen by a random number generator seeded with `seed` whenever a value
+    /// is stored or regenerated, so caches on different machines (with different seeds) will not
+    /// all regenerate their values at the same time. Each time a valid value is retrieved with
+    /// `TimedCache::get`, the time it was stored is reset, sliding the window forward by that
+    /// value's own jittered time to live; the jitter is not chosen again until the value expires
+    /// and is regenerated. Read-only retrievals, such as `TimedCache::get_if_present`, do not
+    /// slide the window.
+    ///
+    pub fn with_jittered_sliding_window(
+        time_to_keep: Duration,
+        max_jitter: Duration,
+        seed: u64,
+    ) -> TimedCache<Key, Value> {
+        let mut cache = TimedCache::with_time_to_keep(time_to_keep);
+        cache.sliding = true;
+        cache.jitter = Some(Jitter::new(max_jitter, seed));
+        cache
+    }
+}
+
+impl<Key: Hash + Eq, Value, S: BuildHasher + Clone> TimedCache<Key, Value, S> {
+    ///
+    /// Creates a `TimedCache` with the specified `Duration` as the length of time the values will
+    /// be considered 'valid' after initial storage, which uses `hash_builder` to hash keys.
+    ///
+    pub fn with_hasher(time_to_keep: Duration, hash_builder: S) -> TimedCache<Key, Value, S> {
         TimedCache {
             time_to_keep,
-            store: HashMap::new(),
+            store: HashMap::with_hasher(hash_builder.clone()),
+            key_stats: HashMap::with_hasher(hash_builder.clone()),
+            initialized: HashMap::with_hasher(hash_builder),
+            on_miss: None,
+            on_evict: EvictionNotifier {
+                callback: None,
+                count: 0,
+            },
+            on_pressure: None,
+            sliding: false,
+            jitter: None,
+            capacity: None,
+            uses: 0,
+            regeneration_cooldown: None,
+            generation_backoff: None,
+            clock: Box::new(SystemClock),
+            hit_observer: None,
+            miss_observer: None,
+            size_samples: None,
+            min_ttl: None,
+            clamped_ttls: 0,
+            auto_purge: None,
+            generations: 0,
+            serve_stale_on_error: false,
+            uncacheable: None,
+            ttl_rules: Vec::new(),
+            uncached: None,
+            miss_log: None,
+            adaptive_ttl: None,
+            regeneration_tracking: None,
+            validator: None,
+            validation_failures: 0,
+            generation_latencies: VecDeque::new(),
         }
     }
 
     ///
-    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
-    /// is still considered valid, otherwise calls `generate_value` to generate the value to
-    /// store in the cache and returns a reference to that value.
+    /// Rebuilds the cache's storage to hold only the entries which are still considered valid,
+    /// with no more capacity than they need, returning how many expired entries were dropped.
     ///
-    pub fn get(&mut self, key: &Key, generate_value: impl Fn() -> Value) -> &Value {
-        // NOTE(zac):
-        // I tried to write this using `Option`s, but ran into borrow checker problems.
-        // So this is what I ended up with.
-        // TODO(zac): See if, in the future, you can convert this to use `Option`s without
-        // the borrow checker throwing a fit.
-        if self.present_and_valid(key) {
-            &self.store[key].item
-        } else {
-            self.insert_and_retrieve(key, generate_value)
+    /// This does the work of `TimedCache::purge_expired` and shrinking the storage in one
+    /// rebuild, which is cheaper when most entries have expired.
+    ///
+    pub fn compact(&mut self) -> usize {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let valid = self
+            .store
+            .values()
+            .filter(|timed_data| timed_data.still_valid_at(time_to_keep, now))
+            .count();
+        let hash_builder = self.store.hasher().clone();
+        let store = mem::replace(
+            &mut self.store,
+            HashMap::with_capacity_and_hasher(valid, hash_builder),
+        );
+        let mut dropped = 0;
+        for (key, timed_data) in store {
+            if timed_data.still_valid_at(time_to_keep, now) {
+                let _ = self.store.insert(key, timed_data);
+            } else {
+                let reason = EvictionReason::Expired;
+                notify_evicted(&mut self.on_evict, &key, &timed_data.item, reason);
+                dropped += 1;
+            }
         }
+        dropped
     }
 
-    fn present_and_valid(&self, key: &Key) -> bool {
-        self.store
-            .get(key)
-            .filter(|timed_data| timed_data.still_valid(self.time_to_keep))
-            .is_some()
+    ///
+    /// Freezes the cache into a `FrozenTimedCache`, which can only look up the values already
+    /// stored, so can be shared between threads without a lock, such as after warming the cache
+    /// at startup.
+    ///
+    /// Every entry keeps the time it has left according to the cache's clock, so expires just as
+    /// it would have, even though the frozen cache checks expiry against the system's monotonic
+    /// clock.
+    ///
+    pub fn freeze(mut self) -> FrozenTimedCache<Key, Value, S> {
+        let store = self.take_store_on_system_clock();
+        FrozenTimedCache::new(self.time_to_keep, store)
     }
 
-    fn insert_and_retrieve(&mut self, key: &Key, generate_value: impl Fn() -> Value) -> &Value {
-        let value = generate_value();
-        // Throw away any old value, it's not important for this use case.
-        let _ = self.store.insert(key.clone(), TimedData::new(value));
-        &self.store[key].item
+    ///
+    /// Freezes the cache into a `FrozenSortedCache`, which holds its entries in a `Vec` sorted
+    /// by key and finds them by binary search, for lower overhead and better locality than
+    /// `TimedCache::freeze` when only lookups are needed.
+    ///
+    /// Every entry keeps the time it has left according to the cache's clock, as with
+    /// `TimedCache::freeze`.
+    ///
+    pub fn into_sorted_vec(mut self) -> FrozenSortedCache<Key, Value>
+    where
+        Key: Ord,
+    {
+        let store = self.take_store_on_system_clock();
+        FrozenSortedCache::new(self.time_to_keep, store.into_iter().collect())
+    }
+
+    ///
+    /// Takes every entry out of the cache, moving the instant each was stored from the cache's
+    /// clock onto the system's monotonic clock, so that each has the same time left by either.
+    ///
+    fn take_store_on_system_clock(&mut self) -> HashMap<Key, TimedData<Value>, S> {
+        let hash_builder = self.store.hasher().clone();
+        let mut store = mem::replace(&mut self.store, HashMap::with_hasher(hash_builder));
+        let old_now = self.clock.now();
+        let new_now = Instant::now();
+        for timed_data in store.values_mut() {
+            timed_data.time_stored = rebase_instant(timed_data.time_stored, old_now, new_now);
+            timed_data.time_generated = rebase_instant(timed_data.time_generated, old_now, new_now);
+        }
+        store
+    }
+
+    ///
+    /// Converts the cache into a `CompactTimedCache`, which stores each entry's expiry in whole
+    /// seconds to take less memory, such as after loading a great many small values.
+    ///
+    /// Each entry keeps the time it has left according to the cache's clock, rounded down to the
+    /// second, and entries which are no longer valid are evicted, with `EvictionReason::Expired`.
+    /// From then on, as the compact cache checks expiry against the system's monotonic clock,
+    /// time left is counted from the system's current instant.
+    ///
+    pub fn compress_timestamps(mut self) -> CompactTimedCache<Key, Value, S> {
+        let hash_builder = self.store.hasher().clone();
+        let store = mem::replace(&mut self.store, HashMap::with_hasher(hash_builder.clone()));
+        let time_to_keep = self.time_to_keep;
+        let now = self.clock.now();
+        let epoch = Instant::now();
+        let mut compact = HashMap::with_capacity_and_hasher(store.len(), hash_builder);
+        for (key, timed_data) in store {
+            match timed_data.remaining_at(time_to_keep, now) {
+                Some(remaining) => {
+                    let expires_at = expiry_tick(epoch, epoch, remaining);
+                    let _ = compact.insert(key, (timed_data.item, expires_at));
+                }
+                None => {
+                    let reason = EvictionReason::Expired;
+                    notify_evicted(&mut self.on_evict, &key, &timed_data.item, reason);
+                }
+            }
+        }
+        CompactTimedCache::new(time_to_keep, epoch, compact)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::TimedCache;
-    use std::sync::Mutex;
-    use std::thread::sleep;
-    use std::time::Duration;
+///
+/// The methods which never clone a key, so can be used with keys which are not `Clone`.
+///
+impl<Key: Hash + Eq, Value, S: BuildHasher> TimedCache<Key, Value, S> {
+    ///
+    /// Sets a callback to be invoked for each entry evicted from the cache, such as to release
+    /// resources held by the value.
+    ///
+    /// An entry is evicted whenever it leaves the cache: when its value is overwritten, when it
+    /// is removed (even if the value is also returned to the caller), when an expired entry is
+    /// purged, and when the cache itself is dropped. To find out which of these happened, use
+    /// `TimedCache::set_on_evict_with_reason` instead.
+    ///
+    pub fn set_on_evict(&mut self, mut on_evict: impl FnMut(&Key, &Value) + Send + 'static) {
+        self.on_evict.callback = Some(Box::new(move |key, value, _| on_evict(key, value)));
+    }
 
-    const KEY: &str = "test";
+    ///
+    /// Sets a callback to be invoked for each entry evicted from the cache, along with the
+    /// reason it was evicted.
+    ///
+    pub fn set_on_evict_with_reason(
+        &mut self,
+        on_evict: impl FnMut(&Key, &Value, EvictionReason) + Send + 'static,
+    ) {
+        self.on_evict.callback = Some(Box::new(on_evict));
+    }
 
-    struct TestService(usize);
+    ///
+    /// Sets a callback to be invoked with the number of entries stored and the capacity whenever
+    /// storing a value for a new key in a full cache evicts an entry, such as to shed load
+    /// upstream under sustained pressure rather than silently thrashing.
+    ///
+    /// The callback is invoked before the entry is evicted, so the number of entries it is given
+    /// is always the capacity. It is not invoked by `TimedCache::set_capacity` shrinking the
+    /// cache.
+    ///
+    pub fn set_on_pressure(&mut self, on_pressure: impl Fn(usize, usize) + Send + 'static) {
+        self.on_pressure = Some(Box::new(on_pressure));
+    }
 
-    impl TestService {
-        fn next(&mut self) -> usize {
-            let n = self.0;
-            self.0 += 1;
-            n
+    ///
+    /// Bounds the cache to hold at most `capacity` entries, immediately evicting entries if it
+    /// currently holds more.
+    ///
+    /// Once bounded, storing a value for a new key in a full cache first evicts an entry. Expired
+    /// entries go first, so a valid entry is never evicted while an expired one lingers: the
+    /// least recently used expired entry is evicted with `EvictionReason::Expired` if there is
+    /// one, and otherwise the least recently used entry with `EvictionReason::Capacity`. An entry
+    /// is used whenever its value is stored, or retrieved by `TimedCache::get` and the other
+    /// methods which can generate a value. Every use is ordered, so which entry is evicted is
+    /// always deterministic, even for entries stored at the same instant.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    ///
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "capacity must be non-zero");
+        self.capacity = Some(capacity);
+        while self.store.len() > capacity {
+            self.evict_for_capacity();
         }
     }
 
-    #[test]
-    fn should_create_test_from_duration() {
-        TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(3));
+    ///
+    /// Sets the clock the cache reads the current instant from, which is the `SystemClock` by
+    /// default.
+    ///
+    /// This is mostly useful in tests: setting a clone of a `ManualClock` lets the test advance
+    /// time, after the cache is constructed, to make values expire without waiting. The clock
+    /// should be set before any values are stored, as values already stored keep the instant
+    /// they were stored at according to the previous clock. To swap clocks once values are
+    /// stored, use `TimedCache::rebase_clock`.
+    ///
+    pub fn set_clock(&mut self, clock: impl Clock + Send + 'static) {
+        self.clock = Box::new(clock);
     }
 
-    #[test]
-    fn should_contain_same_value_for_key_within_duration() {
-        let service = Mutex::new(TestService(0));
-        let generate_value = || service.lock().unwrap().next();
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid. Unlike `TimedCache::get`, this never generates a value.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<&Value> {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .filter(|timed_data| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|timed_data| &timed_data.item)
+    }
 
-        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid, borrowing the cache immutably. This is the same as
+    /// `TimedCache::get_if_present`, and is intended to follow a call to `TimedCache::ensure`.
+    ///
+    pub fn peek(&self, key: &Key) -> Option<&Value> {
+        self.get_if_present(key)
+    }
 
-        let a = *cache.get(&KEY.to_owned(), generate_value);
-        let b = *cache.get(&KEY.to_owned(), generate_value);
+    ///
+    /// Returns the number of entries stored in the cache, including any which are no longer
+    /// considered valid but have not yet been removed.
+    ///
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
 
-        assert_eq!(a, 0);
-        assert_eq!(b, 0);
+    ///
+    /// Returns whether the cache has no entries stored, valid or otherwise.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
     }
 
-    #[test]
-    fn should_contain_different_value_for_key_after_duration() {
-        let service = Mutex::new(TestService(0));
-        let generate_value = || service.lock().unwrap().next();
+    ///
+    /// Stores the `value` in the cache for the `key`, resetting the time it was stored, and
+    /// returns the value previously stored for the `key`, whether or not it was still valid.
+    ///
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        let timed_data = self.new_timed_data(&key, value);
+        self.insert_timed_data(key, timed_data)
+    }
 
-        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(5));
+    fn insert_timed_data(&mut self, key: Key, timed_data: TimedData<Value>) -> Option<Value> {
+        self.make_room_for(&key);
+        match self.store.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let previous = entry.insert(timed_data);
+                let reason = EvictionReason::Overwritten;
+                notify_evicted(&mut self.on_evict, entry.key(), &previous.item, reason);
+                Some(previous.item)
+            }
+            Entry::Vacant(entry) => {
+                let _ = entry.insert(timed_data);
+                None
+            }
+        }
+    }
 
-        let a = *cache.get(&KEY.to_owned(), generate_value);
-        sleep(Duration::from_millis(5));
-        let b = *cache.get(&KEY.to_owned(), generate_value);
+    ///
+    /// Removes the entry for the `key`, returning its value whether or not it was still valid.
+    ///
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
 
-        assert_ne!(a, b);
-        assert_eq!(a, 0);
-        assert_eq!(b, 1);
+    fn remove_entry(&mut self, key: &Key) -> Option<(Key, Value)> {
+        let (key, timed_data) = self.store.remove_entry(key)?;
+        notify_evicted(
+            &mut self.on_evict,
+            &key,
+            &timed_data.item,
+            EvictionReason::Removed,
+        );
+        Some((key, timed_data.item))
+    }
+
+    ///
+    /// Removes every entry from the cache, keeping the per-key hit and miss statistics.
+    ///
+    /// Use `TimedCache::reset` to also start the statistics afresh.
+    ///
+    pub fn clear(&mut self) {
+        let on_evict = &mut self.on_evict;
+        self.store.drain().for_each(|(key, timed_data)| {
+            notify_evicted(on_evict, &key, &timed_data.item, EvictionReason::Removed)
+        });
+    }
+
+    ///
+    /// Iterates over every entry in the cache that is still considered valid, in an arbitrary
+    /// order.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> + '_ {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        self.store
+            .iter()
+            .filter(move |(_, timed_data)| timed_data.still_valid_at(time_to_keep, now))
+            .map(|(key, timed_data)| (key, &timed_data.item))
+    }
+
+    ///
+    /// Folds every entry that is still considered valid into one value, starting from `init`,
+    /// such as to sum the values without collecting them.
+    ///
+    /// Entries are visited in an arbitrary order, as with `TimedCache::iter`.
+    ///
+    pub fn fold_valid<B>(&self, init: B, mut f: impl FnMut(B, &Key, &Value) -> B) -> B {
+        self.iter()
+            .fold(init, |accumulated, (key, value)| f(accumulated, key, value))
+    }
+
+    ///
+    /// Removes every entry that is no longer considered valid, returning how many were removed.
+    ///
+    /// Each removed entry is passed to the eviction callback, or sent down the eviction channel,
+    /// with `EvictionReason::Expired`, so a maintenance thread calling this regularly also
+    /// releases whatever the expired values hold.
+    ///
+    pub fn purge_expired(&mut self) -> usize {
+        let len_before = self.store.len();
+        self.retain_valid(|_, _| true);
+        len_before - self.store.len()
+    }
+
+    ///
+    /// Keeps only the entries which are still considered valid and for which `f` returns `true`,
+    /// removing all others in a single pass.
+    ///
+    pub fn retain_valid(&mut self, mut f: impl FnMut(&Key, &Value) -> bool) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let on_evict = &mut self.on_evict;
+        self.store.retain(|key, timed_data| {
+            let reason = if !timed_data.still_valid_at(time_to_keep, now) {
+                EvictionReason::Expired
+            } else if !f(key, &timed_data.item) {
+                EvictionReason::Removed
+            } else {
+                return true;
+            };
+            notify_evicted(on_evict, key, &timed_data.item, reason);
+            false
+        });
     }
 
+    ///
+    /// Purges expired entries if an automatic purge is due, then evicts an entry if storing a
+    /// value for the `key` would take the cache over its capacity.
+    ///
+    /// This must be called before the value is stored, so that the cache never holds more than
+    /// its capacity, even while the eviction callback runs.
+    ///
+    fn make_room_for(&mut self, key: &Key) {
+        self.auto_purge_if_due();
+        if let Some(capacity) = self.capacity {
+            if self.store.len() >= capacity && !self.store.contains_key(key) {
+                if let Some(ref on_pressure) = self.on_pressure {
+                    on_pressure(self.store.len(), capacity);
+                }
+                self.evict_for_capacity();
+            }
+            debug_assert!(
+                self.store.len() < capacity || self.store.contains_key(key),
+                "no room was made for the key"
+            );
+        }
+    }
+
+    ///
+    /// Purges expired entries if automatic purging is enabled, it is time to check again, and
+    /// enough entries have expired.
+    ///
+    fn auto_purge_if_due(&mut self) {
+        let threshold = match self.auto_purge {
+            Some((threshold, ref mut stores_since_check)) => {
+                *stores_since_check += 1;
+                if *stores_since_check < AUTO_PURGE_CHECK_INTERVAL {
+                    return;
+                }
+                *stores_since_check = 0;
+                threshold
+            }
+            None => return,
+        };
+        let now = self.clock.now();
+        let expired = self
+            .store
+            .values()
+            .filter(|timed_data| !timed_data.still_valid_at(self.time_to_keep, now))
+            .count();
+        if expired as f32 > threshold * self.store.len() as f32 {
+            let _ = self.purge_expired();
+        }
+    }
+
+    ///
+    /// Evicts one entry to make room: the least recently used expired entry if there is one,
+    /// otherwise the least recently used entry.
+    ///
+    fn evict_for_capacity(&mut self) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let rank = |timed_data: &TimedData<Value>| {
+            let valid = timed_data.still_valid_at(time_to_keep, now);
+            (valid, timed_data.last_used, timed_data.generation_id)
+        };
+        let to_evict = match self.store.values().map(rank).min() {
+            Some(to_evict) => to_evict,
+            None => return,
+        };
+        let (valid, _, _) = to_evict;
+        let reason = if valid {
+            EvictionReason::Capacity
+        } else {
+            EvictionReason::Expired
+        };
+        // Remove the entry by its rank rather than its key, so the key need not be cloned.
+        let on_evict = &mut self.on_evict;
+        let mut evicted = false;
+        self.store.retain(|key, timed_data| {
+            if evicted || rank(timed_data) != to_evict {
+                return true;
+            }
+            notify_evicted(on_evict, key, &timed_data.item, reason);
+            evicted = true;
+            false
+        });
+    }
+
+    fn new_timed_data(&mut self, key: &Key, value: Value) -> TimedData<Value> {
+        let now = self.clock.now();
+        self.new_timed_data_at(key, value, now)
+    }
+
+    fn new_timed_data_at(&mut self, key: &Key, value: Value, now: Instant) -> TimedData<Value> {
+        let rule_ttl = rule_ttl(&self.ttl_rules, key);
+        let time_to_keep = self.time_to_keep;
+        let mut timed_data = new_timed_data(&mut self.jitter, time_to_keep, rule_ttl, value, now);
+        timed_data.last_used = self.next_use();
+        timed_data.generation_id = timed_data.last_used;
+        timed_data
+    }
+
+    fn next_use(&mut self) -> u64 {
+        self.uses += 1;
+        self.uses
+    }
+
+    ///
+    /// Returns the time to live the adaptive time to live gives the `timed_data` about to replace
+    /// the value stored for the `key`, or `None` if it does not adapt it.
+    ///
+    fn adapted_ttl(&mut self, key: &Key, timed_data: &TimedData<Value>) -> Option<Duration> {
+        let (max_ttl, eq) = self.adaptive_ttl?;
+        let previous = self.store.get(key)?;
+        if timed_data.time_to_live.is_some() || !eq(&previous.item, &timed_data.item) {
+            return None;
+        }
+        let previous_ttl = match previous.time_to_live {
+            Some(ttl) if previous.ttl_adapted => ttl,
+            _ => self.time_to_keep,
+        };
+        Some(self.clamp_ttl(previous_ttl.saturating_mul(2).min(max_ttl)))
+    }
+
+    ///
+    /// Raises the `ttl` to the minimum time to live, if one is set and the `ttl` is shorter.
+    ///
+    fn clamp_ttl(&mut self, ttl: Duration) -> Duration {
+        match self.min_ttl {
+            Some(min_ttl) if ttl < min_ttl => {
+                self.clamped_ttls = self.clamped_ttls.saturating_add(1);
+                min_ttl
+            }
+            _ => ttl,
+        }
+    }
+
+    ///
+    /// Sets the generator used by `TimedCache::get_or_compute` to regenerate a value for a key
+    /// that is missing or no longer valid.
+    ///
+    pub fn set_on_miss(&mut self, generate_value: impl Fn(&Key) -> Value + Send + 'static) {
+        self.on_miss = Some(Box::new(generate_value));
+    }
+
+    ///
+    /// Sets an observer to be invoked with the key whenever `TimedCache::get`, or one of the other
+    /// methods which can generate a value, finds a valid value stored for it.
+    ///
+    pub fn observe_hits(&mut self, observer: impl Fn(&Key) + Send + 'static) {
+        self.hit_observer = Some(Box::new(observer));
+    }
+
+    ///
+    /// Sets an observer to be invoked with the key whenever `TimedCache::get`, or one of the other
+    /// methods which can generate a value, has to generate the value for it.
+    ///
+    pub fn observe_misses(&mut self, observer: impl Fn(&Key) + Send + 'static) {
+        self.miss_observer = Some(Box::new(observer));
+    }
+
+    ///
+    /// Sets the clock the cache reads the current instant from, like `TimedCache::set_clock`, but
+    /// shifts every instant the cache has recorded so that it is as far from the new clock's
+    /// current instant as it was from the previous clock's.
+    ///
+    /// Every stored value keeps its age and remaining time to live across the swap, so clocks can
+    /// be swapped safely at any time, such as from the `SystemClock` to a `ManualClock` partway
+    /// through a test. An instant too far in the past to be represented relative to the new
+    /// clock is brought forward until it can be, so a value stored that long ago seems younger.
+    ///
+    pub fn rebase_clock(&mut self, clock: impl Clock + Send + 'static) {
+        let old_now = self.clock.now();
+        let new_now = clock.now();
+        let rebase = |instant: &mut Instant| *instant = rebase_instant(*instant, old_now, new_now);
+        for timed_data in self.store.values_mut() {
+            rebase(&mut timed_data.time_stored);
+            rebase(&mut timed_data.time_generated);
+        }
+        for key_stats in self.key_stats.values_mut() {
+            if let Some(ref mut backoff_until) = key_stats.backoff_until {
+                rebase(backoff_until);
+            }
+        }
+        if let Some((ref mut samples, _)) = self.size_samples {
+            for &mut (ref mut instant, _) in samples.iter_mut() {
+                rebase(instant);
+            }
+        }
+        if let Some((ref mut misses, _)) = self.miss_log {
+            for &mut (ref mut instant, _) in misses.iter_mut() {
+                rebase(instant);
+            }
+        }
+        self.clock = Box::new(clock);
+    }
+
+    ///
+    /// Sets the shortest time to live any value in the cache can have, guarding against a
+    /// misconfigured time to live so short it effectively disables caching.
+    ///
+    /// Any shorter time to live is raised to `min_ttl`: the cache's time to keep, any time to
+    /// live already given to an entry, and any given to entries later, such as with
+    /// `TimedCache::set_entry_ttl`. Each time to live raised is counted in
+    /// `TimedCache::clamped_ttls`.
+    ///
+    pub fn set_min_ttl(&mut self, min_ttl: Duration) {
+        self.min_ttl = Some(min_ttl);
+        let mut clamped = 0;
+        let mut clamp = |ttl: &mut Duration| {
+            if *ttl < min_ttl {
+                *ttl = min_ttl;
+                clamped += 1;
+            }
+        };
+        clamp(&mut self.time_to_keep);
+        for timed_data in self.store.values_mut() {
+            if let Some(ref mut time_to_live) = timed_data.time_to_live {
+                clamp(time_to_live);
+            }
+        }
+        for &mut (_, ref mut ttl) in &mut self.ttl_rules {
+            clamp(ttl);
+        }
+        self.clamped_ttls = self.clamped_ttls.saturating_add(clamped);
+    }
+
+    ///
+    /// Returns how many times to live have been raised to the minimum set with
+    /// `TimedCache::set_min_ttl`, such as to alert on a configuration giving times to live which
+    /// are too short. The cache has no logging of its own, so this is how clamping is reported.
+    ///
+    pub fn clamped_ttls(&self) -> u64 {
+        self.clamped_ttls
+    }
+
+    ///
+    /// Enables purging expired entries automatically while storing values, whenever the
+    /// proportion of entries which have expired is above `threshold` (between `0.0` and `1.0`).
+    ///
+    /// Checking the proportion means looking at every entry, so to keep the cost of storing a
+    /// value low it is only checked once every 64 values stored; the cost is amortized over
+    /// those. This keeps memory bounded without a background thread or calls to
+    /// `TimedCache::purge_expired`. A purge can remove the expired value of the very key being
+    /// stored, in which case `TimedCache::insert` returns `None` rather than that value.
+    ///
+    pub fn set_auto_purge(&mut self, threshold: f32) {
+        self.auto_purge = Some((threshold, 0));
+    }
+
+    ///
+    /// Shifts the time the cache sees forward `by` the given duration, as if that much time had
+    /// passed, such as for an integration test to jump past the time to keep without setting up
+    /// a `ManualClock`.
+    ///
+    /// This only affects this cache, and builds on any clock set with `TimedCache::set_clock`.
+    /// It is only available in tests, or with the `testing` feature enabled.
+    ///
+    #[cfg(any(test, feature = "testing"))]
+    pub fn advance_time_for_test(&mut self, by: Duration) {
+        let clock = mem::replace(&mut self.clock, Box::new(SystemClock));
+        self.clock = Box::new(OffsetClock { clock, offset: by });
+    }
+
+    ///
+    /// Sets a minimum time between a value being stored and it being regenerated, as a coarse
+    /// guard against regeneration stampedes.
+    ///
+    /// Until the `cooldown` has passed since a value was stored (or last regenerated),
+    /// `TimedCache::get` and the other methods which can generate a value keep serving it, even
+    /// if it has expired. A cooldown no longer than the time to keep has no effect. Methods which
+    /// never generate a value, such as `TimedCache::get_if_present`, still treat the value as
+    /// expired.
+    ///
+    pub fn set_regeneration_cooldown(&mut self, cooldown: Duration) {
+        self.regeneration_cooldown = Some(cooldown);
+    }
+
+    ///
+    /// Sets how long `TimedCache::try_get_with_backoff` waits before generating the value for a
+    /// key again after the generator fails, to protect a failing backend from being hammered.
+    ///
+    /// After the first failure in a row for a key, no generation is attempted for `initial`.
+    /// Each further failure in a row doubles the backoff, up to `max`. A successful generation
+    /// resets the key, so its next failure backs off for `initial` again.
+    ///
+    pub fn set_generation_backoff(&mut self, initial: Duration, max: Duration) {
+        self.generation_backoff = Some((initial, max));
+    }
+
+    ///
+    /// Marks the keys matching `predicate` as uncacheable, such as administrative keys which must
+    /// always see fresh data.
+    ///
+    /// For an uncacheable key, `TimedCache::get` and the other methods which can generate a value
+    /// call the generator every time and never store what it returns. Values stored explicitly,
+    /// such as with `TimedCache::insert`, are still stored.
+    ///
+    pub fn set_uncacheable(&mut self, predicate: impl Fn(&Key) -> bool + Send + 'static) {
+        self.uncacheable = Some(Box::new(predicate));
+    }
+
+    ///
+    /// Adds a rule giving values stored for keys matching `predicate` a time to live of `ttl`
+    /// instead of the cache's time to keep, such as a shorter time to live for keys with a
+    /// particular prefix.
+    ///
+    /// Rules are checked in the order they were added whenever a value is stored, and the first
+    /// which matches the key decides its time to live; a key matching none gets the time to keep.
+    /// A time to live given explicitly, such as with `TimedCache::get_or_insert_with_ttl`, overrides
+    /// any rule. A `ttl` shorter than any minimum set with `TimedCache::set_min_ttl` is raised
+    /// to the minimum.
+    ///
+    pub fn add_ttl_rule(
+        &mut self,
+        predicate: impl Fn(&Key) -> bool + Send + 'static,
+        ttl: Duration,
+    ) {
+        let ttl = self.clamp_ttl(ttl);
+        self.ttl_rules.push((Box::new(predicate), ttl));
+    }
+
+    ///
+    /// Enables adaptive times to live, so that data which rarely changes is regenerated less
+    /// often.
+    ///
+    /// Whenever `TimedCache::get`, or one of the other methods which can generate a value,
+    /// regenerates a value equal to the one it replaces, the new value is given double the time
+    /// to live of the old one, up to `max_ttl`. A value which differs from the one it replaces
+    /// gets the usual time to live, starting the growth again.
+    ///
+    /// Only values which would get the time to keep are adapted: a value given a time to live of
+    /// its own, such as by `TimedCache::add_ttl_rule` or jitter, keeps it. The grown time to live
+    /// is still raised to the minimum set with `TimedCache::set_min_ttl`, even past `max_ttl`.
+    ///
+    pub fn set_adaptive_ttl(&mut self, max_ttl: Duration)
+    where
+        Value: PartialEq,
+    {
+        self.adaptive_ttl = Some((max_ttl, <Value as PartialEq>::eq));
+    }
+
+    ///
+    /// Sets a check each generated value must pass before it replaces the value already stored,
+    /// such as that configuration loaded from disk still parses.
+    ///
+    /// When `TimedCache::get`, or one of the other methods which can generate a value, generates
+    /// a value which fails the `validator`, the value already stored for the key is kept as it
+    /// was and returned instead, even if it has expired, and the failure is counted in
+    /// `TimedCache::validation_failures`. The kept value stays expired, so the next retrieval
+    /// generates again. A value failing the check is still stored if there is nothing to keep.
+    /// Values stored explicitly, such as with `TimedCache::insert`, are never checked.
+    ///
+    pub fn set_validator(&mut self, validator: impl Fn(&Value) -> bool + Send + 'static) {
+        self.validator = Some(Box::new(validator));
+    }
+
+    ///
+    /// Returns how many generated values have failed the check set with
+    /// `TimedCache::set_validator`.
+    ///
+    pub fn validation_failures(&self) -> u64 {
+        self.validation_failures
+    }
+
+    ///
+    /// Enables tracking how often regenerating a value leaves it unchanged, as reported by
+    /// `TimedCache::unchanged_regeneration_rate`.
+    ///
+    /// This resets any counts already tracked.
+    ///
+    pub fn enable_regeneration_tracking(&mut self)
+    where
+        Value: PartialEq,
+    {
+        self.regeneration_tracking = Some((<Value as PartialEq>::eq, 0, 0));
+    }
+
+    ///
+    /// Sets whether `TimedCache::smart_get` returns the expired value stored for a key, rather
+    /// than the error, when its generator fails. This is off by default.
+    ///
+    pub fn set_serve_stale_on_error(&mut self, serve_stale_on_error: bool) {
+        self.serve_stale_on_error = serve_stale_on_error;
+    }
+
+    ///
+    /// Checks whether a valid value is stored in the cache for every one of the `keys`, stopping
+    /// at the first which has none. This is `true` if there are no `keys`.
+    ///
+    pub fn contains_all(&self, keys: &[Key]) -> bool {
+        keys.iter().all(|key| self.get_if_present(key).is_some())
+    }
+
+    ///
+    /// Checks whether a valid value is stored in the cache for any of the `keys`, stopping at the
+    /// first which has one. This is `false` if there are no `keys`.
+    ///
+    pub fn contains_any(&self, keys: &[Key]) -> bool {
+        keys.iter().any(|key| self.get_if_present(key).is_some())
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid, otherwise returns the reason it could not be retrieved. This
+    /// never generates a value.
+    ///
+    pub fn require(&self, key: &Key) -> Result<&Value, CacheMiss> {
+        let now = self.clock.now();
+        match self.store.get(key) {
+            Some(timed_data) if timed_data.still_valid_at(self.time_to_keep, now) => {
+                Ok(&timed_data.item)
+            }
+            Some(_) => Err(CacheMiss::Expired),
+            None => Err(CacheMiss::Absent),
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` whether or not it is
+    /// still considered valid, along with `true` if it is still valid or `false` if it is stale.
+    ///
+    pub fn get_any(&self, key: &Key) -> Option<(&Value, bool)> {
+        let now = self.clock.now();
+        self.store.get(key).map(|timed_data| {
+            (
+                &timed_data.item,
+                timed_data.still_valid_at(self.time_to_keep, now),
+            )
+        })
+    }
+
+    ///
+    /// Returns a fingerprint of the value stored in the cache for the `key` if it exists and is
+    /// still considered valid, such as for downstream consumers to detect when it changes.
+    ///
+    /// The fingerprint is a hash of the value, so it stays the same when the value is
+    /// regenerated as an equal value and (almost certainly) changes when it is regenerated as a
+    /// different one. It is computed with a fixed hasher, so it is stable for the life of the
+    /// program, but is not guaranteed to be the same between builds.
+    ///
+    pub fn fingerprint(&self, key: &Key) -> Option<u64>
+    where
+        Value: Hash,
+    {
+        self.get_if_present(key).map(|value| {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        })
+    }
+
+    ///
+    /// Behaves like `TimedCache::insert`, but records the value as stored at `time_stored`
+    /// rather than now, such as to restore a persisted entry so it expires on its original
+    /// schedule.
+    ///
+    /// A `time_stored` in the past can give an entry which has already expired. One in the future
+    /// gives an entry which stays valid for longer than its time to live.
+    ///
+    pub fn insert_with_timestamp(
+        &mut self,
+        key: Key,
+        value: Value,
+        time_stored: Instant,
+    ) -> Option<Value> {
+        let mut timed_data = self.new_timed_data(&key, value);
+        timed_data.time_stored = time_stored;
+        timed_data.time_generated = time_stored;
+        self.insert_timed_data(key, timed_data)
+    }
+
+    ///
+    /// Stores the `value` in the cache for the `key` only if it differs from the valid value
+    /// already stored, returning whether it was stored.
+    ///
+    /// When refreshing the cache from repeated polls of an upstream source, this keeps the time
+    /// the value was stored as the time it last actually changed, rather than the time of the
+    /// last poll. An expired value is always replaced, even by an equal one, so that the entry
+    /// becomes valid again.
+    ///
+    pub fn insert_if_changed(&mut self, key: Key, value: Value) -> bool
+    where
+        Value: PartialEq,
+    {
+        let now = self.clock.now();
+        let unchanged = self.store.get(&key).map_or(false, |timed_data| {
+            timed_data.still_valid_at(self.time_to_keep, now) && timed_data.item == value
+        });
+        if !unchanged {
+            let _ = self.insert(key, value);
+        }
+        !unchanged
+    }
+
+    ///
+    /// Behaves like `TimedCache::insert_with_timestamp`, but takes the wall-clock time the value
+    /// was stored at, such as one read back from a persisted snapshot, since an `Instant` cannot
+    /// be persisted.
+    ///
+    /// The age of the value is measured against the system's wall clock. If that clock has moved
+    /// backwards since `stored_at`, so that `stored_at` appears to be in the future, the value is
+    /// treated as having just been stored rather than failing. A `stored_at` so long ago that it
+    /// cannot be represented as an `Instant` gives a value which has already expired.
+    ///
+    pub fn insert_with_system_time(
+        &mut self,
+        key: Key,
+        value: Value,
+        stored_at: SystemTime,
+    ) -> Option<Value> {
+        let age = SystemTime::now()
+            .duration_since(stored_at)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let mut timed_data = self.new_timed_data(&key, value);
+        match timed_data.time_stored.checked_sub(age) {
+            Some(time_stored) => {
+                timed_data.time_stored = time_stored;
+                timed_data.time_generated = time_stored;
+            }
+            None => timed_data.time_to_live = Some(Duration::from_secs(0)),
+        }
+        self.insert_timed_data(key, timed_data)
+    }
+
+    ///
+    /// Behaves like `TimedCache::insert_with_timestamp` for each of the `entries`, such as to
+    /// restore a whole persisted snapshot at once. Any values these replace are discarded.
+    ///
+    pub fn insert_all_with_timestamps(
+        &mut self,
+        entries: impl IntoIterator<Item = (Key, Value, Instant)>,
+    ) {
+        let entries = entries.into_iter();
+        self.store.reserve(entries.size_hint().0);
+        for (key, value, time_stored) in entries {
+            let _ = self.insert_with_timestamp(key, value, time_stored);
+        }
+    }
+
+    ///
+    /// Replaces the value stored for the `key` with `value` without resetting the time it was
+    /// stored, so it still expires when the replaced value would have. Returns `false`, storing
+    /// nothing, if there is no value stored for the `key`.
+    ///
+    /// This is for correcting a stored value in place; use `TimedCache::insert` to store a value
+    /// with a fresh time to live.
+    ///
+    pub fn overwrite_value(&mut self, key: &Key, value: Value) -> bool {
+        let generation_id = self.next_use();
+        let now = self.clock.now();
+        match self.store.get_mut(key) {
+            Some(timed_data) => {
+                let previous = mem::replace(&mut timed_data.item, value);
+                timed_data.generation_id = generation_id;
+                timed_data.time_generated = now;
+                let reason = EvictionReason::Overwritten;
+                notify_evicted(&mut self.on_evict, key, &previous, reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Stores the `value` in the cache for the `key`, resetting the time it was stored, and
+    /// returns a reference to the newly stored value.
+    ///
+    /// This is useful for keeping the cache up to date immediately after writing a value to the
+    /// backing store it caches.
+    ///
+    pub fn write_through(&mut self, key: Key, value: Value) -> &Value {
+        self.make_room_for(&key);
+        let timed_data = self.new_timed_data(&key, value);
+        match self.store.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let previous = entry.insert(timed_data);
+                let reason = EvictionReason::Overwritten;
+                notify_evicted(&mut self.on_evict, entry.key(), &previous.item, reason);
+                &entry.into_mut().item
+            }
+            Entry::Vacant(entry) => &entry.insert(timed_data).item,
+        }
+    }
+
+    ///
+    /// Estimates the number of bytes held by the cache, using `value_size` to report any
+    /// additional memory owned by each value (such as the heap buffer of a `String`).
+    ///
+    /// This is intentionally approximate: it counts the inline size of each stored key and entry,
+    /// plus a byte per entry for the map's own bookkeeping, and ignores unused map capacity and
+    /// allocator overhead.
+    ///
+    pub fn estimated_memory(&self, value_size: impl Fn(&Value) -> usize) -> usize {
+        let entry_size = mem::size_of::<Key>() + mem::size_of::<TimedData<Value>>() + 1;
+        self.store
+            .values()
+            .map(|timed_data| entry_size + value_size(&timed_data.item))
+            .sum()
+    }
+
+    ///
+    /// Iterates over mutable references to every value stored in the cache, whether or not it is
+    /// still considered valid, such as to migrate the stored values in place.
+    ///
+    /// This does not change the time any value was stored, so each expires as it would have.
+    ///
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Value> + '_ {
+        self.store
+            .values_mut()
+            .map(|timed_data| &mut timed_data.item)
+    }
+
+    ///
+    /// Iterates over every entry in the cache that is still considered valid, in sorted key
+    /// order.
+    ///
+    /// The cache is backed by a `HashMap` rather than an ordered map, so this sorts the valid
+    /// entries afresh on every call, taking O(n log n) time; `TimedCache::iter` and
+    /// `TimedCache::keys` stay unordered.
+    ///
+    pub fn iter_sorted(&self) -> impl Iterator<Item = (&Key, &Value)> + '_
+    where
+        Key: Ord,
+    {
+        self.iter().collect::<BTreeMap<_, _>>().into_iter()
+    }
+
+    ///
+    /// Iterates over the keys of every entry in the cache that is still considered valid, in
+    /// sorted order.
+    ///
+    /// Like `TimedCache::iter_sorted`, this sorts the keys afresh on every call.
+    ///
+    pub fn keys_sorted(&self) -> impl Iterator<Item = &Key> + '_
+    where
+        Key: Ord,
+    {
+        self.iter_sorted().map(|(key, _)| key)
+    }
+
+    ///
+    /// Regenerates the value for every key stored in the cache using `generate_value`, resetting
+    /// the time each value was stored.
+    ///
+    /// If `refresh_expired` is `false`, entries that are no longer valid are dropped from the
+    /// cache instead of being regenerated.
+    ///
+    pub fn refresh_all(&mut self, generate_value: impl Fn(&Key) -> Value, refresh_expired: bool) {
+        let now = self.clock.now();
+        if !refresh_expired {
+            let _ = self.purge_expired();
+        }
+        let time_to_keep = self.time_to_keep;
+        let ttl_rules = &self.ttl_rules;
+        let jitter = &mut self.jitter;
+        let on_evict = &mut self.on_evict;
+        let uses = &mut self.uses;
+        let refreshed = self.store.len();
+        self.store.iter_mut().for_each(|(key, timed_data)| {
+            let rule_ttl = rule_ttl(ttl_rules, key);
+            let value = generate_value(key);
+            let mut refreshed = new_timed_data(jitter, time_to_keep, rule_ttl, value, now);
+            refreshed.last_used = timed_data.last_used;
+            *uses += 1;
+            refreshed.generation_id = *uses;
+            let previous = mem::replace(timed_data, refreshed);
+            notify_evicted(on_evict, key, &previous.item, EvictionReason::Overwritten);
+        });
+        self.count_generations(refreshed as u64);
+    }
+
+    ///
+    /// Removes the entry for the `key` and returns its value only if it is still considered
+    /// valid. An expired entry is left in place, and `None` returned.
+    ///
+    pub fn take_valid(&mut self, key: &Key) -> Option<Value> {
+        if self.present_and_valid(key) {
+            self.remove(key)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Removes the entries for each of the `keys`, returning the removed value (or `None`, if
+    /// there was no entry) for each key in the same order as the `keys`.
+    ///
+    pub fn remove_many(&mut self, keys: &[Key]) -> Vec<Option<Value>> {
+        keys.iter().map(|key| self.remove(key)).collect()
+    }
+
+    ///
+    /// Removes every entry from the cache and resets the per-key hit and miss statistics, such as
+    /// to start a fresh measurement window.
+    ///
+    pub fn reset(&mut self) {
+        self.clear();
+        self.key_stats.clear();
+    }
+
+    ///
+    /// Returns every entry in the cache that is still considered valid and whose key satisfies
+    /// `pred`, in an arbitrary order.
+    ///
+    pub fn get_matching(&self, pred: impl Fn(&Key) -> bool) -> Vec<(&Key, &Value)> {
+        self.iter().filter(|(key, _)| pred(key)).collect()
+    }
+
+    ///
+    /// Calls `f` with every entry that is no longer considered valid, leaving the cache
+    /// untouched, such as to inspect expired entries before calling `TimedCache::purge_expired`.
+    ///
+    pub fn for_each_expired(&self, mut f: impl FnMut(&Key, &Value)) {
+        let now = self.clock.now();
+        self.store
+            .iter()
+            .filter(|(_, timed_data)| !timed_data.still_valid_at(self.time_to_keep, now))
+            .for_each(|(key, timed_data)| f(key, &timed_data.item));
+    }
+
+    ///
+    /// Removes every entry stored more than `age` ago, whatever its time to live, returning how
+    /// many were removed.
+    ///
+    /// This is a one-off sweep for freshness: it does not change the time to keep of the cache.
+    ///
+    pub fn expire_older_than(&mut self, age: Duration) -> usize {
+        let now = self.clock.now();
+        let on_evict = &mut self.on_evict;
+        let len_before = self.store.len();
+        self.store.retain(|key, timed_data| {
+            let keep = now.saturating_duration_since(timed_data.time_stored) <= age;
+            if !keep {
+                notify_evicted(on_evict, key, &timed_data.item, EvictionReason::Expired);
+            }
+            keep
+        });
+        len_before - self.store.len()
+    }
+
+    ///
+    /// Sets the length of time the value stored for the `key` will be considered 'valid',
+    /// overriding the cache's time to keep for that entry alone. Returns `false` if there is no
+    /// value stored for the `key`.
+    ///
+    /// This does not reset the time the value was stored, so the new `ttl` is measured from when
+    /// the value was originally stored. The override lasts until the value is replaced. A `ttl`
+    /// shorter than any minimum set with `TimedCache::set_min_ttl` is raised to the minimum.
+    ///
+    pub fn set_entry_ttl(&mut self, key: &Key, ttl: Duration) -> bool {
+        let ttl = self.clamp_ttl(ttl);
+        match self.store.get_mut(key) {
+            Some(timed_data) => {
+                timed_data.time_to_live = Some(ttl);
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Removes every entry for which `pred` returns `true`, whether or not it is still valid, and
+    /// returns the removed keys and values.
+    ///
+    pub fn extract_if(&mut self, mut pred: impl FnMut(&Key, &Value) -> bool) -> Vec<(Key, Value)> {
+        let on_evict = &mut self.on_evict;
+        extract_matching(&mut self.store, |key, timed_data| {
+            pred(key, &timed_data.item)
+        })
+        .into_iter()
+        .map(|(key, timed_data)| {
+            notify_evicted(on_evict, &key, &timed_data.item, EvictionReason::Removed);
+            (key, timed_data.item)
+        })
+        .collect()
+    }
+
+    ///
+    /// Extends the time to live of every value in the cache which is still considered valid by
+    /// `by`, such as to keep serving everything cached while the backend it caches is down.
+    ///
+    /// This only affects values stored now: values stored afterwards get the usual time to live.
+    /// Values which have already expired stay expired.
+    ///
+    pub fn extend_all(&mut self, by: Duration) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        for timed_data in self.store.values_mut() {
+            if timed_data.still_valid_at(time_to_keep, now) {
+                let time_to_live = timed_data.time_to_live.unwrap_or(time_to_keep);
+                timed_data.time_to_live = Some(time_to_live.saturating_add(by));
+            }
+        }
+    }
+
+    ///
+    /// Removes every entry that is no longer considered valid, appending it to `out`, and
+    /// returns how many were removed.
+    ///
+    /// Unlike `TimedCache::purge_expired`, the removed values are handed back rather than
+    /// dropped, and reusing the same `out` across calls avoids allocating for every sweep. Each
+    /// removed entry is still passed to the eviction callback with `EvictionReason::Expired`.
+    ///
+    pub fn drain_expired_into(&mut self, out: &mut Vec<(Key, Value)>) -> usize {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let on_evict = &mut self.on_evict;
+        let len_before = out.len();
+        out.extend(
+            extract_matching(&mut self.store, |_, timed_data| {
+                !timed_data.still_valid_at(time_to_keep, now)
+            })
+            .into_iter()
+            .map(|(key, timed_data)| {
+                notify_evicted(on_evict, &key, &timed_data.item, EvictionReason::Expired);
+                (key, timed_data.item)
+            }),
+        );
+        out.len() - len_before
+    }
+
+    ///
+    /// Removes every entry whose key matches `pred`, whether or not it is still valid, and
+    /// returns how many were removed.
+    ///
+    pub fn invalidate_matching(&mut self, pred: impl Fn(&Key) -> bool) -> usize {
+        self.extract_if(|key, _| pred(key)).len()
+    }
+
+    ///
+    /// Removes every entry whose key is within `range`, whether or not it is still valid, and
+    /// returns how many were removed.
+    ///
+    /// This is not the efficient range removal an ordered store would allow: the cache is backed
+    /// by a `HashMap`, so this checks every entry, taking O(n) time however few are in the range,
+    /// just like `TimedCache::invalidate_matching`.
+    ///
+    pub fn clear_range(&mut self, range: impl RangeBounds<Key>) -> usize
+    where
+        Key: Ord,
+    {
+        self.invalidate_matching(|key| range.contains(key))
+    }
+
+    ///
+    /// Removes each entry with probability `fraction`, whether or not it is still valid, and
+    /// returns how many were removed, such as to simulate losing part of the cache in tests.
+    ///
+    /// The entries are chosen by a random number generator seeded with `seed`, so the number of
+    /// entries removed from caches of the same size with the same seed is always the same.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between `0.0` and `1.0`.
+    ///
+    pub fn expire_random_fraction(&mut self, fraction: f64, seed: u64) -> usize {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "fraction must be between 0 and 1"
+        );
+        let mut rng = SplitMix64::new(seed);
+        self.extract_if(|_, _| rng.next_f64() < fraction).len()
+    }
+
+    ///
+    /// Returns the entry which was stored the longest time ago, whether or not it is still valid,
+    /// or `None` if the cache is empty.
+    ///
+    /// Of entries stored at the same instant, the one stored first counts as the oldest, so the
+    /// order is always deterministic.
+    ///
+    pub fn oldest(&self) -> Option<(&Key, &Value)> {
+        self.store
+            .iter()
+            .min_by_key(|(_, timed_data)| (timed_data.time_stored, timed_data.generation_id))
+            .map(|(key, timed_data)| (key, &timed_data.item))
+    }
+
+    ///
+    /// Returns the entry which was stored most recently, whether or not it is still valid, or
+    /// `None` if the cache is empty.
+    ///
+    /// Of entries stored at the same instant, the one stored last counts as the newest.
+    ///
+    pub fn newest(&self) -> Option<(&Key, &Value)> {
+        self.store
+            .iter()
+            .max_by_key(|(_, timed_data)| (timed_data.time_stored, timed_data.generation_id))
+            .map(|(key, timed_data)| (key, &timed_data.item))
+    }
+
+    ///
+    /// Evicts every entry except the `n` most recently stored, returning how many were evicted,
+    /// as a cheap way to bound the cache by hand without setting a capacity.
+    ///
+    /// Of entries stored at the same instant, those stored later count as newer. Each evicted
+    /// entry is reported to the eviction callback with `EvictionReason::Capacity`.
+    ///
+    pub fn retain_newest(&mut self, n: usize) -> usize {
+        let rank =
+            |timed_data: &TimedData<Value>| (timed_data.time_stored, timed_data.generation_id);
+        let mut ranks: Vec<_> = self.store.values().map(rank).collect();
+        if ranks.len() <= n {
+            return 0;
+        }
+        ranks.sort_unstable_by_key(|&rank| Reverse(rank));
+        let oldest_kept = n.checked_sub(1).map(|index| ranks[index]);
+        let on_evict = &mut self.on_evict;
+        let len_before = self.store.len();
+        self.store.retain(|key, timed_data| {
+            let evict = oldest_kept.map_or(true, |oldest| rank(timed_data) < oldest);
+            if evict {
+                notify_evicted(on_evict, key, &timed_data.item, EvictionReason::Capacity);
+            }
+            !evict
+        });
+        len_before - self.store.len()
+    }
+
+    ///
+    /// Converts the cache into one keyed by a different type, applying `f` to every key while
+    /// keeping each value along with the time it was stored and any time to live of its own.
+    ///
+    /// The time to keep, sliding window and jitter carry over to the new cache, but the per-key
+    /// statistics and any generator or eviction callback do not, as they refer to the old keys.
+    /// If `f` maps several keys to the same new key, only the entry processed last is kept; the
+    /// order entries are processed in is unspecified.
+    ///
+    pub fn map_keys<K2: Hash + Eq>(mut self, f: impl Fn(Key) -> K2) -> TimedCache<K2, Value> {
+        let mut cache = TimedCache::with_time_to_keep(self.time_to_keep);
+        cache.sliding = self.sliding;
+        cache.jitter = self.jitter.take();
+        cache.capacity = self.capacity;
+        cache.uses = self.uses;
+        cache.clock = mem::replace(&mut self.clock, Box::new(SystemClock));
+        cache.store = self
+            .store
+            .drain()
+            .map(|(key, timed_data)| (f(key), timed_data))
+            .collect();
+        cache
+    }
+
+    ///
+    /// Returns the earliest instant at which an entry in the cache stops being valid, or `None`
+    /// if the cache is empty.
+    ///
+    /// Entries whose time to live is too long for their expiry to be represented as an `Instant`
+    /// (such as a time to keep of `Duration::MAX`) never expire, so are not considered. If every
+    /// entry is like this, `None` is returned.
+    ///
+    /// An event loop can sleep until this instant and then call `TimedCache::purge_expired`,
+    /// rather than polling. If some entries have already expired, the instant returned will be
+    /// in the past.
+    ///
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.store
+            .values()
+            .filter_map(|timed_data| timed_data.expires_at(self.time_to_keep))
+            .min()
+    }
+
+    ///
+    /// Takes the cache apart into its time to keep and a map of the entries which are still
+    /// considered valid. Expired entries are dropped.
+    ///
+    /// The times the values were stored are not kept, so a cache rebuilt from the parts treats
+    /// every value as freshly stored.
+    ///
+    pub fn into_parts(mut self) -> (Duration, HashMap<Key, Value>) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let on_evict = &mut self.on_evict;
+        let valid = self
+            .store
+            .drain()
+            .filter(|(key, timed_data)| {
+                let valid = timed_data.still_valid_at(time_to_keep, now);
+                if !valid {
+                    notify_evicted(on_evict, key, &timed_data.item, EvictionReason::Expired);
+                }
+                valid
+            })
+            .map(|(key, timed_data)| (key, timed_data.item))
+            .collect();
+        (time_to_keep, valid)
+    }
+
+    ///
+    /// Consumes the cache, yielding each entry which is still considered valid and dropping the
+    /// rest, in an arbitrary order.
+    ///
+    pub fn into_valid(self) -> impl Iterator<Item = (Key, Value)> {
+        let (_, valid) = self.into_parts();
+        valid.into_iter()
+    }
+
+    ///
+    /// Returns how long ago the value for the `key` was stored, or `None` if there is no value
+    /// stored for the `key`.
+    ///
+    pub fn age(&self, key: &Key) -> Option<Duration> {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .map(|timed_data| now.saturating_duration_since(timed_data.time_stored))
+    }
+
+    ///
+    /// Returns how much longer the value stored for the `key` will be considered valid, or zero
+    /// if there is no value stored for the `key` or it is no longer valid.
+    ///
+    pub fn remaining_ttl_or_zero(&self, key: &Key) -> Duration {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .and_then(|timed_data| timed_data.remaining_at(self.time_to_keep, now))
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Attaches `metadata` to the value stored for the `key`, whether or not it is still valid,
+    /// such as where the value was fetched from, without it being part of the value itself.
+    /// Returns `false`, attaching nothing, if there is no value stored for the `key`.
+    ///
+    /// Any metadata already attached is replaced, whatever its type. Metadata belongs to the
+    /// value it was attached to, so it is dropped when that value is replaced or removed.
+    ///
+    pub fn set_metadata<M: Any + Send + Sync>(&mut self, key: &Key, metadata: M) -> bool {
+        match self.store.get_mut(key) {
+            Some(timed_data) => {
+                timed_data.metadata = Some(Box::new(metadata));
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Returns the metadata attached to the value stored for the `key` with
+    /// `TimedCache::set_metadata`, or `None` if there is none or it is not of type `M`.
+    ///
+    pub fn metadata<M: Any>(&self, key: &Key) -> Option<&M> {
+        self.store
+            .get(key)
+            .and_then(|timed_data| timed_data.metadata.as_ref())
+            .and_then(|metadata| metadata.downcast_ref())
+    }
+
+    ///
+    /// Returns the generation id of the value stored for the `key`, or `None` if there is no
+    /// value stored for the `key`.
+    ///
+    /// Every time a value is stored or regenerated it is given a new id, greater than any id
+    /// given before it by this cache, so a changed id means the value was replaced. Ids are not
+    /// consecutive, and retrieving a value never changes its id.
+    ///
+    pub fn generation_id(&self, key: &Key) -> Option<u64> {
+        self.store
+            .get(key)
+            .map(|timed_data| timed_data.generation_id)
+    }
+
+    ///
+    /// Retrieves a reference to the valid value stored for the `key` along with its generation
+    /// id, but only if that id is greater than `since`, such as to cheaply detect whether the
+    /// value has been regenerated since it was last seen.
+    ///
+    pub fn get_if_generation_changed(&self, key: &Key, since: u64) -> Option<(&Value, u64)> {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .filter(|timed_data| timed_data.still_valid_at(self.time_to_keep, now))
+            .filter(|timed_data| timed_data.generation_id > since)
+            .map(|timed_data| (&timed_data.item, timed_data.generation_id))
+    }
+
+    ///
+    /// Returns how long it has been since the value for the `key` was last generated (or
+    /// stored), or `None` if there is no value stored for the `key`.
+    ///
+    /// Comparing this against the time to keep shows how much of its lifetime each value
+    /// actually uses, which is useful when tuning the time to keep. Unlike `TimedCache::age`,
+    /// this is not reset by retrieving a valid value from a cache with a sliding window.
+    ///
+    pub fn time_since_regeneration(&self, key: &Key) -> Option<Duration> {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .map(|timed_data| now.saturating_duration_since(timed_data.time_generated))
+    }
+
+    ///
+    /// Returns whether the value stored for the `key` is no longer considered valid, or `None` if
+    /// there is no value stored for the `key`.
+    ///
+    pub fn is_expired(&self, key: &Key) -> Option<bool> {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .map(|timed_data| !timed_data.still_valid_at(self.time_to_keep, now))
+    }
+
+    ///
+    /// Counts the valid entries in the cache by how soon they expire, returning the start of each
+    /// non-empty bucket of remaining time to live along with its count, in ascending order.
+    ///
+    /// An entry whose remaining time to live is `remaining` is counted in the bucket starting at
+    /// the largest multiple of `bucket` no greater than `remaining`. Many entries in the lowest
+    /// buckets means many values are about to be regenerated at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is zero.
+    ///
+    pub fn remaining_ttl_histogram(&self, bucket: Duration) -> Vec<(Duration, usize)> {
+        assert!(bucket > Duration::from_secs(0), "bucket must not be zero");
+        let now = self.clock.now();
+        let mut counts = BTreeMap::new();
+        for timed_data in self.store.values() {
+            if let Some(remaining) = timed_data.remaining_at(self.time_to_keep, now) {
+                *counts.entry(bucket_index(remaining, bucket)).or_insert(0) += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(index, count)| (bucket_start(index, bucket), count))
+            .collect()
+    }
+
+    ///
+    /// Buckets every entry stored in the cache, whether or not it is still valid, by how long ago
+    /// it was stored, returning the start of each non-empty bucket of age along with its count,
+    /// in ascending order.
+    ///
+    /// Buckets are assigned as in `TimedCache::remaining_ttl_histogram`, but by age rather than
+    /// remaining time to live, so entries which have long expired are counted too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is zero.
+    ///
+    pub fn age_histogram(&self, bucket: Duration) -> Vec<(Duration, usize)> {
+        assert!(bucket > Duration::from_secs(0), "bucket must not be zero");
+        let now = self.clock.now();
+        let mut counts = BTreeMap::new();
+        for timed_data in self.store.values() {
+            let age = now.saturating_duration_since(timed_data.time_stored);
+            *counts.entry(bucket_index(age, bucket)).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(index, count)| (bucket_start(index, bucket), count))
+            .collect()
+    }
+
+    ///
+    /// Enables sampling the number of entries in the cache with `TimedCache::record_size_sample`,
+    /// keeping only the most recent `max_samples` samples.
+    ///
+    /// This discards any samples already recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_samples` is zero.
+    ///
+    pub fn enable_size_sampling(&mut self, max_samples: usize) {
+        assert!(max_samples > 0, "max_samples must be non-zero");
+        self.size_samples = Some((VecDeque::with_capacity(max_samples), max_samples));
+    }
+
+    ///
+    /// Records the number of entries in the cache, as returned by `TimedCache::len`, along with
+    /// the current instant, discarding the oldest sample if the maximum are already kept.
+    ///
+    /// This does nothing unless sampling has been enabled with
+    /// `TimedCache::enable_size_sampling`.
+    ///
+    pub fn record_size_sample(&mut self) {
+        let now = self.clock.now();
+        let len = self.store.len();
+        if let Some((ref mut samples, max_samples)) = self.size_samples {
+            if samples.len() == max_samples {
+                let _ = samples.pop_front();
+            }
+            samples.push_back((now, len));
+        }
+    }
+
+    ///
+    /// Iterates over the samples recorded by `TimedCache::record_size_sample`, oldest first.
+    ///
+    pub fn size_samples(&self) -> impl Iterator<Item = (Instant, usize)> + '_ {
+        self.size_samples
+            .iter()
+            .flat_map(|(samples, _)| samples.iter().cloned())
+    }
+
+    ///
+    /// Estimates the `q` quantile of how long generations have taken, such as `0.5` for the median
+    /// or `0.99` for the 99th percentile, or returns `None` if nothing has been generated yet.
+    ///
+    /// Only the most recent 1024 generations timed after a miss are considered, so the estimate
+    /// follows changes in latency over time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between `0.0` and `1.0`.
+    ///
+    pub fn generation_latency_percentile(&self, q: f64) -> Option<Duration> {
+        assert!((0.0..=1.0).contains(&q), "q must be between 0 and 1");
+        if self.generation_latencies.is_empty() {
+            return None;
+        }
+        let mut latencies: Vec<Duration> = self.generation_latencies.iter().cloned().collect();
+        latencies.sort();
+        let rank = (q * (latencies.len() - 1) as f64).round() as usize;
+        Some(latencies[rank])
+    }
+
+    ///
+    /// Returns the fraction, between `0.0` and `1.0`, of the regenerations tracked since
+    /// `TimedCache::enable_regeneration_tracking` which produced a value equal to the one it
+    /// replaced, or `0.0` if none have been tracked.
+    ///
+    /// Only values generated by `TimedCache::get`, or one of the other methods which can
+    /// generate a value, in place of one already stored are counted. A rate close to `1.0`
+    /// suggests the time to keep could be lengthened.
+    ///
+    pub fn unchanged_regeneration_rate(&self) -> f64 {
+        match self.regeneration_tracking {
+            Some((_, regenerations, unchanged)) if regenerations > 0 => {
+                unchanged as f64 / regenerations as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    ///
+    /// Enables logging the key of each miss along with the instant it happened, keeping only the
+    /// most recent `capacity` misses, such as to find keys which keep missing because their time
+    /// to keep is too short.
+    ///
+    /// This discards any misses already logged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    ///
+    pub fn enable_miss_log(&mut self, capacity: usize) {
+        assert!(capacity > 0, "capacity must be non-zero");
+        self.miss_log = Some((VecDeque::with_capacity(capacity), capacity));
+    }
+
+    ///
+    /// Iterates over the misses logged since `TimedCache::enable_miss_log` was called, oldest
+    /// first, which is nothing if the miss log is not enabled.
+    ///
+    pub fn recent_misses(&self) -> impl Iterator<Item = (Instant, &Key)> + '_ {
+        self.miss_log
+            .iter()
+            .flat_map(|(misses, _)| misses.iter().map(|&(instant, ref key)| (instant, key)))
+    }
+
+    ///
+    /// Returns the number of `(hits, misses)` recorded for the `key` by `TimedCache::get` and
+    /// `TimedCache::get_or_compute`, or `None` if the key has never been retrieved.
+    ///
+    /// These counts describe the key itself, so they are kept even when the value is regenerated.
+    /// Each saturates at `u64::MAX` rather than overflowing.
+    ///
+    pub fn key_stats(&self, key: &Key) -> Option<(u64, u64)> {
+        self.key_stats
+            .get(key)
+            .map(|key_stats| (key_stats.hits, key_stats.misses))
+    }
+
+    ///
+    /// Returns up to `n` valid keys with the most accesses recorded for them, along with their
+    /// access counts, most accessed first, such as to see the working set when choosing a
+    /// capacity.
+    ///
+    /// A key's access count is its hits plus its misses, as returned by `TimedCache::key_stats`.
+    /// Keys with equal counts are returned in an arbitrary order.
+    ///
+    pub fn hot_keys(&self, n: usize) -> Vec<(&Key, u64)> {
+        let mut keys = self.valid_key_accesses();
+        keys.sort_by_key(|(_, accesses)| Reverse(*accesses));
+        keys.truncate(n);
+        keys
+    }
+
+    ///
+    /// Returns up to `n` valid keys with the fewest accesses recorded for them, least accessed
+    /// first, such as to choose which entries to evict by hand under memory pressure.
+    ///
+    /// Access counts are the same as for `TimedCache::hot_keys`. Keys with equal counts are
+    /// returned in an arbitrary order.
+    ///
+    pub fn cold_keys(&self, n: usize) -> Vec<&Key> {
+        let mut keys = self.valid_key_accesses();
+        keys.sort_by_key(|(_, accesses)| *accesses);
+        keys.into_iter().take(n).map(|(key, _)| key).collect()
+    }
+
+    ///
+    /// Returns how long the generator took the last time the value for the `key` was generated
+    /// after a miss, or `None` if it has never been.
+    ///
+    /// This is measured with the system's monotonic clock, whatever clock the cache has been
+    /// given with `TimedCache::set_clock`. Values generated by `TimedCache::refresh_all` or
+    /// `TimedCache::prewarm` are not timed, as they are not generated after a miss.
+    ///
+    pub fn last_generation_time(&self, key: &Key) -> Option<Duration> {
+        self.key_stats
+            .get(key)
+            .and_then(|key_stats| key_stats.last_generation_time)
+    }
+
+    ///
+    /// Returns how many times the cache has called a generator over its lifetime, such as to
+    /// account for the cost of each generation.
+    ///
+    /// Every call to a generator passed to (or set on) the cache is counted once it returns:
+    /// each miss in `TimedCache::get` and the other methods which generate a value on a miss,
+    /// each attempt made by `TimedCache::try_get_with_retries`, each generator panic caught by
+    /// `TimedCache::try_get_catching`, and each value generated by `TimedCache::refresh_all` and
+    /// `TimedCache::prewarm`. Values stored directly, such as by `TimedCache::insert`, are not
+    /// counted. The count saturates at `u64::MAX`, and is not reset by `TimedCache::reset`.
+    ///
+    pub fn generation_count(&self) -> u64 {
+        self.generations
+    }
+
+    ///
+    /// Returns the cache's statistics at this moment in one self-contained value, such as to
+    /// export to a monitoring system on a timer.
+    ///
+    pub fn stats_snapshot(&self) -> CacheStatsSnapshot {
+        let (hits, misses) =
+            self.key_stats
+                .values()
+                .fold((0u64, 0u64), |(hits, misses), key_stats| {
+                    (
+                        hits.saturating_add(key_stats.hits),
+                        misses.saturating_add(key_stats.misses),
+                    )
+                });
+        CacheStatsSnapshot {
+            hits,
+            misses,
+            generations: self.generations,
+            evictions: self.on_evict.count,
+            len: self.store.len(),
+            valid_len: self.iter().count(),
+        }
+    }
+
+    ///
+    /// Returns every valid key along with the number of hits and misses recorded for it.
+    ///
+    fn valid_key_accesses(&self) -> Vec<(&Key, u64)> {
+        self.iter()
+            .map(|(key, _)| {
+                let accesses = self.key_stats.get(key).map_or(0, |key_stats| {
+                    key_stats.hits.saturating_add(key_stats.misses)
+                });
+                (key, accesses)
+            })
+            .collect()
+    }
+
+    ///
+    /// Records how long it has been since `started` as the time taken to generate the value for
+    /// a `key` which has already had a miss recorded, counting the generation.
+    ///
+    fn record_generation_time(&mut self, key: &Key, started: Instant) {
+        let elapsed = started.elapsed();
+        self.count_generations(1);
+        self.record_generation_latency(elapsed);
+        if let Some(key_stats) = self.key_stats.get_mut(key) {
+            key_stats.last_generation_time = Some(elapsed);
+        }
+    }
+
+    fn record_generation_latency(&mut self, elapsed: Duration) {
+        if self.generation_latencies.len() == GENERATION_LATENCY_SAMPLES {
+            let _ = self.generation_latencies.pop_front();
+        }
+        self.generation_latencies.push_back(elapsed);
+    }
+
+    fn count_generations(&mut self, generations: u64) {
+        self.generations = self.generations.saturating_add(generations);
+    }
+
+    ///
+    /// Checks the invariants the cache relies on internally, for use in tests.
+    ///
+    /// The checks are only made when debug assertions are enabled, like `debug_assert!`; in a
+    /// release build this does nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if debug assertions are enabled and the cache holds more entries than its
+    /// capacity, or the cache gives each value a jittered time to live and an entry does not
+    /// have one.
+    ///
+    pub fn debug_validate(&self) {
+        debug_assert!(
+            self.capacity
+                .map_or(true, |capacity| self.store.len() <= capacity),
+            "cache holds more entries than its capacity"
+        );
+        for timed_data in self.store.values() {
+            debug_assert!(
+                self.jitter.is_none() || timed_data.time_to_live.is_some(),
+                "entry is missing a jittered time to live"
+            );
+        }
+    }
+
+    fn present_and_valid(&self, key: &Key) -> bool {
+        let now = self.clock.now();
+        self.store
+            .get(key)
+            .filter(|timed_data| timed_data.still_valid_at(self.time_to_keep, now))
+            .is_some()
+    }
+
+    ///
+    /// Checks whether the value for the `key` can be served without regenerating it, because it
+    /// is still valid or its regeneration cooldown has not yet passed.
+    ///
+    fn servable(&self, key: &Key) -> bool {
+        self.servable_at(key, self.clock.now())
+    }
+
+    fn servable_at(&self, key: &Key, now: Instant) -> bool {
+        self.store.get(key).map_or(false, |timed_data| {
+            timed_data.still_valid_at(self.time_to_keep, now)
+                || self
+                    .regeneration_cooldown
+                    .map_or(false, |cooldown| timed_data.still_valid_at(cooldown, now))
+        })
+    }
+
+    ///
+    /// Stores the `timed_data` for a `key` given by value, without cloning it, and returns a
+    /// reference to the stored value.
+    ///
+    /// Any value this replaces is passed to the eviction callback: as `Expired` if it had
+    /// expired, which is the usual case after a miss, or as `Overwritten` if it was still valid.
+    ///
+    fn store_owned(&mut self, key: Key, timed_data: TimedData<Value>) -> &mut Value {
+        let now = self.clock.now();
+        self.store_owned_at(key, timed_data, now)
+    }
+
+    ///
+    /// Behaves like `store_owned`, but judges whether any value it replaces was still valid at
+    /// `now` rather than the current instant.
+    ///
+    fn store_owned_at(
+        &mut self,
+        key: Key,
+        timed_data: TimedData<Value>,
+        now: Instant,
+    ) -> &mut Value {
+        if self
+            .uncacheable
+            .as_ref()
+            .map_or(false, |uncacheable| uncacheable(&key))
+        {
+            return self.uncached.insert(timed_data.item);
+        }
+        let rejected = self
+            .validator
+            .as_ref()
+            .map_or(false, |validator| !validator(&timed_data.item));
+        if rejected {
+            self.validation_failures = self.validation_failures.saturating_add(1);
+            if self.store.contains_key(&key) {
+                let previous = self.store.get_mut(&key).expect("key should be present");
+                return &mut previous.item;
+            }
+        }
+        let time_to_keep = self.time_to_keep;
+        let mut timed_data = timed_data;
+        if let Some(ttl) = self.adapted_ttl(&key, &timed_data) {
+            timed_data.time_to_live = Some(ttl);
+            timed_data.ttl_adapted = true;
+        }
+        self.make_room_for(&key);
+        match self.store.entry(key) {
+            Entry::Occupied(mut entry) => {
+                if let Some((eq, ref mut regenerations, ref mut unchanged)) =
+                    self.regeneration_tracking
+                {
+                    *regenerations += 1;
+                    if eq(&entry.get().item, &timed_data.item) {
+                        *unchanged += 1;
+                    }
+                }
+                let previous = entry.insert(timed_data);
+                let reason = if previous.still_valid_at(time_to_keep, now) {
+                    EvictionReason::Overwritten
+                } else {
+                    EvictionReason::Expired
+                };
+                notify_evicted(&mut self.on_evict, entry.key(), &previous.item, reason);
+                &mut entry.into_mut().item
+            }
+            Entry::Vacant(entry) => &mut entry.insert(timed_data).item,
+        }
+    }
+
+    ///
+    /// Returns the entry for the `key`, for modifying a valid value in place or storing a value
+    /// if there is no valid one, in the style of `HashMap::entry`.
+    ///
+    pub fn entry(&mut self, key: Key) -> CacheEntry<'_, Key, Value, S> {
+        CacheEntry::new(self, key)
+    }
+
+    ///
+    /// Returns a read-only view of the cache, which can be given to code that must only look up
+    /// values already stored, never generating them or modifying the cache.
+    ///
+    pub fn read_view(&self) -> ReadView<'_, Key, Value, S> {
+        ReadView::new(self)
+    }
+}
+
+///
+/// The methods which clone keys, such as to store a value for a key given by reference, so need
+/// `Key: Clone`.
+///
+impl<Key: Hash + Eq + Clone, Value, S: BuildHasher> TimedCache<Key, Value, S> {
+    ///
+    /// Sends a copy of each entry evicted from the cache down the `sender`, such as to a thread
+    /// dedicated to cleaning up evicted values.
+    ///
+    /// Entries are sent for every eviction described by `TimedCache::set_on_evict`, and this
+    /// replaces any eviction callback set. If the receiver is dropped, entries are still evicted
+    /// as usual and the errors sending them are ignored.
+    ///
+    pub fn set_eviction_channel(&mut self, sender: Sender<(Key, Value)>)
+    where
+        Key: Send + 'static,
+        Value: Clone + Send + 'static,
+    {
+        self.set_on_evict(move |key, value| {
+            let _ = sender.send((key.clone(), value.clone()));
+        });
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid, otherwise calls `generate_value` to generate the value to
+    /// store in the cache and returns a reference to that value.
+    ///
+    pub fn get(&mut self, key: &Key, generate_value: impl Fn() -> Value) -> &Value {
+        // NOTE(zac):
+        // I tried to write this using `Option`s, but ran into borrow checker problems.
+        // So this is what I ended up with.
+        // TODO(zac): See if, in the future, you can convert this to use `Option`s without
+        // the borrow checker throwing a fit.
+        if self.servable(key) {
+            self.retrieve_hit(key)
+        } else {
+            self.record_miss(key);
+            self.insert_and_retrieve(key, generate_value)
+        }
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but accepts a generator which mutates what it captures,
+    /// such as a round-robin index or a retry counter, without needing a `Mutex` or `Cell`.
+    ///
+    pub fn get_mut_gen(&mut self, key: &Key, mut generate_value: impl FnMut() -> Value) -> &Value {
+        if self.servable(key) {
+            self.retrieve_hit(key)
+        } else {
+            self.record_miss(key);
+            self.insert_and_retrieve(key, &mut generate_value)
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and is
+    /// still considered valid, otherwise stores the `default` in the cache and returns a
+    /// reference to it.
+    ///
+    /// This is for when the value to store is already at hand, so no generator is needed. On a
+    /// hit, the `default` is simply dropped.
+    ///
+    pub fn get_or(&mut self, key: &Key, default: Value) -> &Value {
+        if self.servable(key) {
+            self.retrieve_hit(key)
+        } else {
+            self.record_miss(key);
+            self.store_and_retrieve(key, default)
+        }
+    }
+
+    ///
+    /// Behaves like `TimedCache::get_or`, but returns a clone of whichever value ends up stored:
+    /// the valid value already stored on a hit, or the `value` given on a miss.
+    ///
+    /// This frees the cache to be used again straight away while the value carries on being
+    /// used, at the cost of one clone of the value on every call, hit or miss.
+    ///
+    pub fn get_or_insert_cloned(&mut self, key: &Key, value: Value) -> Value
+    where
+        Value: Clone,
+    {
+        self.get_or(key, value).clone()
+    }
+
+    ///
+    /// Retrieves a reference to the value initialized for the `key`, calling `init` to initialize
+    /// it if this is the first time it has been asked for, like `OnceCell::get_or_init`.
+    ///
+    /// Unlike every other value in the cache, these values ignore the time to keep: `init` is
+    /// called at most once for each key for the lifetime of the cache, and the value it returns
+    /// is kept until the cache is dropped. They are held apart from the entries with a time to
+    /// live, so are not seen by any other method, such as `TimedCache::get` or
+    /// `TimedCache::len`, and are never evicted.
+    ///
+    /// Unlike `OnceCell::get_or_init`, this needs the cache borrowed mutably, as storing the
+    /// value does; `LocalTimedCache::get_or_init` offers the same through a shared reference.
+    ///
+    pub fn get_or_init(&mut self, key: &Key, init: impl FnOnce() -> Value) -> &Value {
+        if !self.initialized.contains_key(key) {
+            let _ = self.initialized.insert(key.clone(), init());
+        }
+        &self.initialized[key]
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but returns a `ValueGuard` which only slides the value's
+    /// window of validity forward if `ValueGuard::commit` is called, such as once the work done
+    /// with the value has succeeded.
+    ///
+    /// Retrieving a valid value through the guard never resets the time it was stored, even if
+    /// the cache has a sliding window.
+    ///
+    /// Returns `None` if the `key` is uncacheable, as there is then no stored value to guard.
+    ///
+    pub fn get_guard(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> Option<ValueGuard<'_, Key, Value, S>> {
+        if self.servable(key) {
+            self.record_hit(key);
+            let last_used = self.next_use();
+            if let Some(timed_data) = self.store.get_mut(key) {
+                timed_data.last_used = last_used;
+            }
+        } else {
+            self.record_miss(key);
+            let _ = self.insert_and_retrieve(key, generate_value);
+        }
+        if !self.store.contains_key(key) {
+            return None;
+        }
+        Some(ValueGuard::new(self, key.clone()))
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but passes the `ctx` to the generator on a miss, such as
+    /// a request-scoped token the generator needs which would be awkward to capture in a
+    /// closure. On a hit, the `ctx` is simply dropped.
+    ///
+    pub fn get_ctx<C>(
+        &mut self,
+        key: &Key,
+        ctx: C,
+        generate_value: impl FnOnce(C) -> Value,
+    ) -> &Value {
+        if self.servable(key) {
+            return self.retrieve_hit(key);
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let value = generate_value(ctx);
+        self.record_generation_time(key, started);
+        self.store_and_retrieve(key, value)
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but also regenerates the value if it was stored more than
+    /// `max_age` ago, for a single retrieval needing fresher data than the time to keep allows.
+    ///
+    /// A `max_age` longer than the time to keep behaves just like `TimedCache::get`.
+    ///
+    pub fn get_fresh_within(
+        &mut self,
+        key: &Key,
+        max_age: Duration,
+        generate_value: impl Fn() -> Value,
+    ) -> &Value {
+        let now = self.clock.now();
+        let fresh_enough = self.store.get(key).map_or(false, |timed_data| {
+            now.saturating_duration_since(timed_data.time_stored) <= max_age
+        });
+        if fresh_enough && self.servable(key) {
+            self.retrieve_hit(key)
+        } else {
+            self.record_miss(key);
+            self.insert_and_retrieve(key, generate_value)
+        }
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but treats `now` as the current instant for this call
+    /// alone, rather than reading the cache's clock.
+    ///
+    /// Whether the stored value is still valid is judged at `now`, and a newly generated value
+    /// is stored as of `now`, so a test can step a single call across the time to keep without
+    /// sleeping or setting a clock. Other housekeeping, such as automatic purging, still uses
+    /// the cache's clock.
+    ///
+    pub fn get_at(
+        &mut self,
+        key: &Key,
+        now: Instant,
+        generate_value: impl Fn() -> Value,
+    ) -> &Value {
+        if self.servable_at(key, now) {
+            return self.retrieve_hit_at(key, now);
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let value = generate_value();
+        self.record_generation_time(key, started);
+        let timed_data = self.new_timed_data_at(key, value, now);
+        self.store_owned_at(key.clone(), timed_data, now)
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but takes ownership of the `key` so that on a miss it can
+    /// be moved into the cache rather than cloned. On a hit, the `key` is simply dropped.
+    ///
+    /// The only clone made is to record hit and miss statistics for a key the first time it is
+    /// seen.
+    ///
+    pub fn get_owned_key(&mut self, key: Key, generate_value: impl Fn() -> Value) -> &Value {
+        if self.servable(&key) {
+            return self.retrieve_hit(&key);
+        }
+        self.record_miss(&key);
+        let started = Instant::now();
+        let value = generate_value();
+        self.record_generation_time(&key, started);
+        let timed_data = self.new_timed_data(&key, value);
+        self.store_owned(key, timed_data)
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and is
+    /// still considered valid, otherwise stores and returns the value returned by `f`, giving it a
+    /// time to live of `ttl` rather than the cache's time to keep.
+    ///
+    /// The time to live of a value already stored is left as it was.
+    ///
+    pub fn get_or_insert_with_ttl(
+        &mut self,
+        key: Key,
+        ttl: Duration,
+        f: impl FnOnce() -> Value,
+    ) -> &Value {
+        if self.servable(&key) {
+            return self.retrieve_hit(&key);
+        }
+        self.record_miss(&key);
+        let started = Instant::now();
+        let value = f();
+        self.record_generation_time(&key, started);
+        let mut timed_data = self.new_timed_data(&key, value);
+        timed_data.time_to_live = Some(self.clamp_ttl(ttl));
+        self.store_owned(key, timed_data)
+    }
+
+    ///
+    /// Behaves like `TimedCache::get_or_insert_with_ttl`, but `f` is given the key and decides
+    /// the time to live along with the value, such as from the expiry a backend returned with
+    /// it.
+    ///
+    /// `f` is only called if no valid value is stored for the `key`.
+    ///
+    pub fn get_or_insert_full(
+        &mut self,
+        key: Key,
+        f: impl FnOnce(&Key) -> (Value, Duration),
+    ) -> &Value {
+        if self.servable(&key) {
+            return self.retrieve_hit(&key);
+        }
+        self.record_miss(&key);
+        let started = Instant::now();
+        let (value, ttl) = f(&key);
+        self.record_generation_time(&key, started);
+        let mut timed_data = self.new_timed_data(&key, value);
+        timed_data.time_to_live = Some(self.clamp_ttl(ttl));
+        self.store_owned(key, timed_data)
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but also reports how the value was retrieved: whether a
+    /// valid value was stored, a new one was generated, or an expired one was served within its
+    /// regeneration cooldown.
+    ///
+    pub fn get_detailed(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> (&Value, GetOutcome) {
+        if self.servable(key) {
+            let outcome = if self.present_and_valid(key) {
+                GetOutcome::Hit
+            } else {
+                GetOutcome::ServedStale
+            };
+            return (self.retrieve_hit(key), outcome);
+        }
+        self.record_miss(key);
+        (
+            self.insert_and_retrieve(key, generate_value),
+            GetOutcome::Generated,
+        )
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but also returns whether the value changed: `true` if a
+    /// value was generated that differs from the one previously stored (or if there was none),
+    /// and `false` if a valid value was found or the regenerated value is equal to the old one.
+    ///
+    /// This lets callers skip downstream work when regeneration produced the same value again.
+    /// The time the value was stored is reset on regeneration either way.
+    ///
+    pub fn get_reporting_changed(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> (&Value, bool)
+    where
+        Value: PartialEq,
+    {
+        if self.servable(key) {
+            return (self.retrieve_hit(key), false);
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let value = generate_value();
+        self.record_generation_time(key, started);
+        let changed = self
+            .store
+            .get(key)
+            .map_or(true, |timed_data| timed_data.item != value);
+        (self.store_and_retrieve(key, value), changed)
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but with a fallible generator, returning its error if it
+    /// fails.
+    ///
+    /// A failed generation never changes what is stored: any entry already stored for the `key`,
+    /// even an expired one, is left exactly as it was, so a transient failure cannot wipe a
+    /// stale value which could still be recovered with `TimedCache::get_any`.
+    ///
+    pub fn try_get<E>(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Result<Value, E>,
+    ) -> Result<&Value, E> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let result = generate_value();
+        self.record_generation_time(key, started);
+        // Only store once the generator has succeeded, so the old entry survives an error.
+        Ok(self.store_and_retrieve(key, result?))
+    }
+
+    ///
+    /// Behaves like `TimedCache::try_get`, but gives a newly generated value the time to live
+    /// returned by `ttl_fn` for it, rather than the cache's time to keep, such as when how long
+    /// an upstream response can be cached is only known once it has been fetched.
+    ///
+    /// A time to live shorter than any minimum set with `TimedCache::set_min_ttl` is raised to
+    /// the minimum. If the generator fails, nothing is stored.
+    ///
+    pub fn try_get_with_ttl_fn<E>(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Result<Value, E>,
+        ttl_fn: impl Fn(&Value) -> Duration,
+    ) -> Result<&Value, E> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let result = generate_value();
+        self.record_generation_time(key, started);
+        let value = result?;
+        let ttl = self.clamp_ttl(ttl_fn(&value));
+        let mut timed_data = self.new_timed_data(key, value);
+        timed_data.time_to_live = Some(ttl);
+        Ok(self.store_owned(key.clone(), timed_data))
+    }
+
+    ///
+    /// Behaves like `TimedCache::try_get`, but if serving stale values on error has been enabled
+    /// with `TimedCache::set_serve_stale_on_error`, a failed generation returns the expired value
+    /// still stored for the `key` instead of the error.
+    ///
+    /// The error is only returned if there is no value stored to fall back on, or falling back is
+    /// disabled. As with `TimedCache::try_get`, a failed generation leaves the stored entry as it
+    /// was, so it keeps being served until a generation succeeds.
+    ///
+    pub fn smart_get<E>(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Result<Value, E>,
+    ) -> Result<&Value, E> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let result = generate_value();
+        self.record_generation_time(key, started);
+        match result {
+            Ok(value) => Ok(self.store_and_retrieve(key, value)),
+            Err(_) if self.serve_stale_on_error && self.store.contains_key(key) => {
+                Ok(&self.store[key].item)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    ///
+    /// Behaves like `TimedCache::try_get`, but backs off generating the value for a `key` whose
+    /// generator keeps failing, as set with `TimedCache::set_generation_backoff`.
+    ///
+    /// While the `key` is backing off, the generator is not called: the expired value still
+    /// stored for the `key` is returned if there is one, and otherwise
+    /// `BackoffError::BackingOff` with how long is left. Without a backoff set, this behaves
+    /// exactly like `TimedCache::try_get`.
+    ///
+    pub fn try_get_with_backoff<E>(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Result<Value, E>,
+    ) -> Result<&Value, BackoffError<E>> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        let now = self.clock.now();
+        let backoff_until = self
+            .key_stats
+            .get(key)
+            .and_then(|key_stats| key_stats.backoff_until)
+            .filter(|backoff_until| now < *backoff_until);
+        if let Some(backoff_until) = backoff_until {
+            return match self.store.get(key) {
+                Some(timed_data) => Ok(&timed_data.item),
+                None => Err(BackoffError::BackingOff(backoff_until - now)),
+            };
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let result = generate_value();
+        self.record_generation_time(key, started);
+        let generation_backoff = self.generation_backoff;
+        let key_stats = self.key_stats.get_mut(key).expect("miss was recorded");
+        match result {
+            Ok(value) => {
+                key_stats.record_success();
+                Ok(self.store_and_retrieve(key, value))
+            }
+            Err(error) => {
+                if let Some((initial, max)) = generation_backoff {
+                    key_stats.record_failure(now, initial, max);
+                }
+                Err(BackoffError::Failed(error))
+            }
+        }
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but catches a panic in `generate_value` and returns it as
+    /// an `Err`, leaving any entry already stored for the `key` untouched.
+    ///
+    /// The generator must be `UnwindSafe`, as any state it captures may be observed after the
+    /// panic. If it captures a reference to something which is not (such as a `RefCell`, or a
+    /// `&mut` reference), and that state cannot be left broken by the panic, wrap the generator
+    /// in `std::panic::AssertUnwindSafe`. The cache itself is never left in an inconsistent
+    /// state, as nothing is stored until the generator returns.
+    ///
+    pub fn try_get_catching(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Value + UnwindSafe,
+    ) -> Result<&Value, Box<dyn Any + Send>> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let value = panic::catch_unwind(generate_value);
+        self.record_generation_time(key, started);
+        let value = value?;
+        Ok(self.store_and_retrieve(key, value))
+    }
+
+    ///
+    /// Behaves like `TimedCache::get`, but with a fallible generator which is retried up to
+    /// `retries` times (so called at most `retries + 1` times) until it succeeds.
+    ///
+    /// The first value generated is stored and returned. If every attempt fails, the last error
+    /// is returned and any entry already stored for the `key` is left untouched.
+    ///
+    pub fn try_get_with_retries<E>(
+        &mut self,
+        key: &Key,
+        generate_value: impl Fn() -> Result<Value, E>,
+        retries: usize,
+    ) -> Result<&Value, E> {
+        if self.servable(key) {
+            return Ok(self.retrieve_hit(key));
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let mut result = generate_value();
+        for _ in 0..retries {
+            if result.is_ok() {
+                break;
+            }
+            self.count_generations(1);
+            result = generate_value();
+        }
+        self.record_generation_time(key, started);
+        Ok(self.store_and_retrieve(key, result?))
+    }
+
+    ///
+    /// Makes sure a valid value is stored in the cache for the `key`, calling `generate_value`
+    /// to generate it if needed, without returning a reference to it.
+    ///
+    /// Because `TimedCache::get` borrows the cache mutably for as long as the returned reference
+    /// is held, no other method can be called on the cache until it is dropped. Calling
+    /// `ensure` followed by `TimedCache::peek` instead leaves only a shared borrow outstanding:
+    ///
+    /// ```
+    /// # use timed_cache::TimedCache;
+    /// # use std::time::Duration;
+    /// let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(60));
+    /// let key = "key".to_owned();
+    /// cache.ensure(&key, || 1);
+    ///
+    /// let value = cache.peek(&key).unwrap();
+    /// assert_eq!(cache.len(), 1);
+    /// assert_eq!(*value, 1);
+    /// ```
+    ///
+    pub fn ensure(&mut self, key: &Key, generate_value: impl Fn() -> Value) {
+        let _ = self.get(key, generate_value);
+    }
+
+    ///
+    /// Maps each of the `keys` to whether a valid value is stored for it, such as to plan which
+    /// keys a batch fetch needs to cover.
+    ///
+    pub fn validity_map(&self, keys: &[Key]) -> HashMap<Key, bool> {
+        keys.iter()
+            .map(|key| (key.clone(), self.get_if_present(key).is_some()))
+            .collect()
+    }
+
+    ///
+    /// Behaves like `TimedCache::get` for both `first` and `second`, generating their values with
+    /// `generate_value` if needed, and returns references to both values at once.
+    ///
+    /// Returns `None` if the cache does not hold both values once `second` is stored: if either
+    /// key is uncacheable, or if making room for `second` evicted `first`, such as when the cache
+    /// is full and `first` is expired but still served during its regeneration cooldown.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `first` and `second` are the same key.
+    ///
+    pub fn get_disjoint(
+        &mut self,
+        first: &Key,
+        second: &Key,
+        generate_value: impl Fn(&Key) -> Value,
+    ) -> Option<(&Value, &Value)> {
+        assert!(first != second, "get_disjoint needs two different keys");
+        self.ensure(first, || generate_value(first));
+        self.ensure(second, || generate_value(second));
+        let first = self.store.get(first)?;
+        let second = self.store.get(second)?;
+        Some((&first.item, &second.item))
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid, otherwise generates the value using the generator set with
+    /// `TimedCache::set_on_miss`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value needs to be generated and no generator has been set.
+    ///
+    pub fn get_or_compute(&mut self, key: &Key) -> &Value {
+        if self.servable(key) {
+            return self.retrieve_hit(key);
+        }
+        self.record_miss(key);
+        let started = Instant::now();
+        let value = match self.on_miss {
+            Some(ref generate_value) => generate_value(key),
+            None => {
+                panic!("TimedCache::get_or_compute called without a generator set by set_on_miss")
+            }
+        };
+        self.record_generation_time(key, started);
+        self.store_and_retrieve(key, value)
+    }
+
+    ///
+    /// Stores the `value` in the cache for the `key`, resetting the time it was stored, and
+    /// returns the value previously stored for the `key` only if it was still valid.
+    ///
+    /// Unlike `TimedCache::insert`, an expired value is discarded rather than returned.
+    ///
+    pub fn swap(&mut self, key: &Key, value: Value) -> Option<Value> {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        self.make_room_for(key);
+        let timed_data = self.new_timed_data(key, value);
+        let previous = self.store.insert(key.clone(), timed_data)?;
+        if previous.still_valid_at(time_to_keep, now) {
+            let reason = EvictionReason::Overwritten;
+            notify_evicted(&mut self.on_evict, key, &previous.item, reason);
+            Some(previous.item)
+        } else {
+            let reason = EvictionReason::Expired;
+            notify_evicted(&mut self.on_evict, key, &previous.item, reason);
+            None
+        }
+    }
+
+    ///
+    /// Returns an owned copy of every entry in the cache that is still considered valid.
+    ///
+    pub fn valid_snapshot(&self) -> HashMap<Key, Value>
+    where
+        Value: Clone,
+    {
+        let now = self.clock.now();
+        self.store
+            .iter()
+            .filter(|(_, timed_data)| timed_data.still_valid_at(self.time_to_keep, now))
+            .map(|(key, timed_data)| (key.clone(), timed_data.item.clone()))
+            .collect()
+    }
+
+    ///
+    /// Returns an owned copy of every entry in the cache that is still considered valid, along
+    /// with how much longer each will be valid for, as a simple form to persist the cache in.
+    ///
+    /// `TimedCache::from_vec` rebuilds a cache from the entries returned.
+    ///
+    pub fn to_vec(&self) -> Vec<(Key, Value, Duration)>
+    where
+        Value: Clone,
+    {
+        let now = self.clock.now();
+        self.store
+            .iter()
+            .filter_map(|(key, timed_data)| {
+                timed_data
+                    .remaining_at(self.time_to_keep, now)
+                    .map(|remaining| (key.clone(), timed_data.item.clone(), remaining))
+            })
+            .collect()
+    }
+
+    ///
+    /// Behaves like `TimedCache::valid_snapshot`, but returns the entries sorted by key, such as
+    /// for reproducible diagnostics.
+    ///
+    /// Like `TimedCache::iter_sorted`, this sorts the entries afresh on every call.
+    ///
+    pub fn sorted_valid_snapshot(&self) -> BTreeMap<Key, Value>
+    where
+        Key: Ord,
+        Value: Clone,
+    {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    ///
+    /// Replaces each expired value still stored in the cache with the result of calling `f` on
+    /// it, such as to swap a large payload for a small placeholder while keeping the entry's
+    /// timing information. Valid values are left untouched.
+    ///
+    /// This does not change the time any value was stored.
+    ///
+    pub fn map_expired_values(&mut self, f: impl Fn(Value) -> Value) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let expired: Vec<Key> = self
+            .store
+            .iter()
+            .filter(|(_, timed_data)| !timed_data.still_valid_at(time_to_keep, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(timed_data) = self.store.remove(&key) {
+                let timed_data = TimedData {
+                    item: f(timed_data.item),
+                    ..timed_data
+                };
+                let _ = self.store.insert(key, timed_data);
+            }
+        }
+    }
+
+    ///
+    /// Returns an owned copy of the keys of every entry in the cache that is still considered
+    /// valid, in an arbitrary order.
+    ///
+    /// As the keys do not borrow the cache, this allows looping over them while calling methods
+    /// which modify the cache, such as `TimedCache::remove`.
+    ///
+    pub fn keys_vec(&self) -> Vec<Key> {
+        self.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    ///
+    /// Behaves like `TimedCache::refresh_all` with `refresh_expired` set, but regenerates only
+    /// the `max_per_call` entries which expire the soonest, or expired the longest time ago,
+    /// returning how many were regenerated.
+    ///
+    /// Calling this repeatedly, such as once per tick of a timer, spreads a full refresh out
+    /// over time rather than regenerating every value at once. The entries closest to expiring
+    /// are always regenerated first, taking into account any time to live of their own.
+    ///
+    pub fn refresh_gradually(
+        &mut self,
+        max_per_call: usize,
+        generate_value: impl Fn(&Key) -> Value,
+    ) -> usize {
+        let time_to_keep = self.time_to_keep;
+        let mut oldest: Vec<(Option<Instant>, Key)> = self
+            .store
+            .iter()
+            .map(|(key, timed_data)| (timed_data.expires_at(time_to_keep), key.clone()))
+            .collect();
+        // An entry expiring too far in the future to represent expires after all the others.
+        oldest.sort_by_key(|&(expires_at, _)| (expires_at.is_none(), expires_at));
+        oldest.truncate(max_per_call);
+        let now = self.clock.now();
+        for (_, key) in &oldest {
+            let value = generate_value(key);
+            let rule_ttl = rule_ttl(&self.ttl_rules, key);
+            let mut refreshed =
+                new_timed_data(&mut self.jitter, time_to_keep, rule_ttl, value, now);
+            let timed_data = self.store.get_mut(key).expect("key should be present");
+            refreshed.last_used = timed_data.last_used;
+            self.uses += 1;
+            refreshed.generation_id = self.uses;
+            let previous = mem::replace(timed_data, refreshed);
+            let reason = EvictionReason::Overwritten;
+            notify_evicted(&mut self.on_evict, key, &previous.item, reason);
+        }
+        self.count_generations(oldest.len() as u64);
+        oldest.len()
+    }
+
+    ///
+    /// Generates and stores a value, using `generate_value`, for each of the `keys` which does
+    /// not already have a valid value stored, returning how many values were generated.
+    ///
+    pub fn prewarm(&mut self, keys: &[Key], generate_value: impl Fn(&Key) -> Value) -> usize {
+        let mut generated = 0;
+        for key in keys {
+            if !self.present_and_valid(key) {
+                let _ = self.store_and_retrieve(key, generate_value(key));
+                generated += 1;
+            }
+        }
+        self.count_generations(generated as u64);
+        generated
+    }
+
+    ///
+    /// Removes and returns the entry which was stored the longest time ago, whether or not it is
+    /// still valid, or `None` if the cache is empty.
+    ///
+    pub fn evict_oldest(&mut self) -> Option<(Key, Value)> {
+        let oldest = self.oldest().map(|(key, _)| key.clone())?;
+        self.remove_entry(&oldest)
+    }
+
+    ///
+    /// Removes and returns the entry which was stored most recently, whether or not it is still
+    /// valid, or `None` if the cache is empty.
+    ///
+    pub fn evict_newest(&mut self) -> Option<(Key, Value)> {
+        let newest = self.newest().map(|(key, _)| key.clone())?;
+        self.remove_entry(&newest)
+    }
+
+    ///
+    /// Evicts entries, least recently used first, until the total weight of the entries left is
+    /// at most `target_weight`, returning how many were evicted, such as to shed memory in
+    /// response to memory pressure.
+    ///
+    /// The weight of each entry is given by `weigh`, which could estimate the bytes it owns as
+    /// with `TimedCache::estimated_memory`, and is weighed only once. Each evicted entry is
+    /// reported to the eviction callback with `EvictionReason::Capacity`, or
+    /// `EvictionReason::Expired` if it was no longer valid.
+    ///
+    pub fn evict_to_weight(
+        &mut self,
+        target_weight: usize,
+        weigh: impl Fn(&Key, &Value) -> usize,
+    ) -> usize {
+        let mut by_last_use: Vec<(u64, usize, Key)> = self
+            .store
+            .iter()
+            .map(|(key, timed_data)| {
+                let weight = weigh(key, &timed_data.item);
+                (timed_data.last_used, weight, key.clone())
+            })
+            .collect();
+        let mut total_weight = by_last_use.iter().fold(0_usize, |total, &(_, weight, _)| {
+            total.saturating_add(weight)
+        });
+        by_last_use.sort_by_key(|&(last_used, _, _)| last_used);
+        let now = self.clock.now();
+        let mut evicted = 0;
+        for (_, weight, key) in by_last_use {
+            if total_weight <= target_weight {
+                break;
+            }
+            let timed_data = self.store.remove(&key).expect("key should be present");
+            total_weight = total_weight.saturating_sub(weight);
+            let reason = if timed_data.still_valid_at(self.time_to_keep, now) {
+                EvictionReason::Capacity
+            } else {
+                EvictionReason::Expired
+            };
+            notify_evicted(&mut self.on_evict, &key, &timed_data.item, reason);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    ///
+    /// Splits the cache into one holding the entries which are still considered valid and one
+    /// holding those which are not, keeping the time each value was stored and any time to live
+    /// of its own.
+    ///
+    /// Both caches have the same time to keep. The cache of valid entries keeps the rest of the
+    /// configuration as `TimedCache::map_keys` does, while the cache of expired entries is given
+    /// the default configuration.
+    ///
+    pub fn partition_expired(mut self) -> (TimedCache<Key, Value>, TimedCache<Key, Value>) {
+        let now = self.clock.now();
+        let time_to_keep = self.time_to_keep;
+        let mut expired = TimedCache::with_time_to_keep(time_to_keep);
+        expired.store = extract_matching(&mut self.store, |_, timed_data| {
+            !timed_data.still_valid_at(time_to_keep, now)
+        })
+        .into_iter()
+        .collect();
+        (self.map_keys(|key| key), expired)
+    }
+
+    ///
+    /// Returns the instant each valid entry expires along with its key, ordered from the entry
+    /// expiring soonest to the one expiring last, such as to show the expiry schedule in a
+    /// debugging UI.
+    ///
+    /// As with `TimedCache::next_expiry`, entries whose expiry is too far in the future to be
+    /// represented as an `Instant` never expire, so are left out.
+    ///
+    pub fn expiry_timeline(&self) -> Vec<(Instant, Key)> {
+        let now = self.clock.now();
+        let mut timeline: Vec<(Instant, Key)> = self
+            .store
+            .iter()
+            .filter(|(_, timed_data)| timed_data.still_valid_at(self.time_to_keep, now))
+            .filter_map(|(key, timed_data)| {
+                timed_data
+                    .expires_at(self.time_to_keep)
+                    .map(|expiry| (expiry, key.clone()))
+            })
+            .collect();
+        timeline.sort_by_key(|(expiry, _)| *expiry);
+        timeline
+    }
+
+    ///
+    /// Groups the keys of all valid entries by how soon they expire, mapping each bucket index to
+    /// the keys in it. An entry's bucket index is its remaining time to live divided by `bucket`,
+    /// so the entries closest to expiring are in the lowest buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is zero.
+    ///
+    pub fn group_by_expiry_bucket(&self, bucket: Duration) -> BTreeMap<u64, Vec<Key>> {
+        assert!(bucket > Duration::from_secs(0), "bucket must not be zero");
+        let now = self.clock.now();
+        let mut groups = BTreeMap::new();
+        for (key, timed_data) in &self.store {
+            if let Some(remaining) = timed_data.remaining_at(self.time_to_keep, now) {
+                groups
+                    .entry(bucket_index(remaining, bucket))
+                    .or_insert_with(Vec::new)
+                    .push(key.clone());
+            }
+        }
+        groups
+    }
+
+    fn record_hit(&mut self, key: &Key) {
+        self.record_access(key, KeyStats::record_hit);
+        if let Some(ref observer) = self.hit_observer {
+            observer(key);
+        }
+    }
+
+    fn record_miss(&mut self, key: &Key) {
+        self.record_access(key, KeyStats::record_miss);
+        if let Some((ref mut misses, capacity)) = self.miss_log {
+            if misses.len() == capacity {
+                let _ = misses.pop_front();
+            }
+            misses.push_back((self.clock.now(), key.clone()));
+        }
+        if let Some(ref observer) = self.miss_observer {
+            observer(key);
+        }
+    }
+
+    fn record_access(&mut self, key: &Key, record: fn(&mut KeyStats)) {
+        // Only clone the key the first time it is seen.
+        match self.key_stats.get_mut(key) {
+            Some(key_stats) => record(key_stats),
+            None => record(self.key_stats.entry(key.clone()).or_default()),
+        }
+    }
+
+    fn insert_and_retrieve(&mut self, key: &Key, generate_value: impl FnOnce() -> Value) -> &Value {
+        let started = Instant::now();
+        let value = generate_value();
+        self.record_generation_time(key, started);
+        self.store_and_retrieve(key, value)
+    }
+
+    fn store_and_retrieve(&mut self, key: &Key, value: Value) -> &Value {
+        let timed_data = self.new_timed_data(key, value);
+        self.store_owned(key.clone(), timed_data)
+    }
+
+    ///
+    /// Records a hit for a `key` known to be present and valid, sliding its window of validity
+    /// if enabled, and returns a reference to its value.
+    ///
+    fn retrieve_hit(&mut self, key: &Key) -> &mut Value {
+        let now = self.clock.now();
+        self.retrieve_hit_at(key, now)
+    }
+
+    fn retrieve_hit_at(&mut self, key: &Key, now: Instant) -> &mut Value {
+        self.record_hit(key);
+        let last_used = self.next_use();
+        let timed_data = self.store.get_mut(key).expect("key should be present");
+        timed_data.last_used = last_used;
+        if self.sliding {
+            timed_data.time_stored = now;
+        }
+        &mut timed_data.item
+    }
+}
+
+///
+/// A cache storing metadata alongside each value, such as the ETag of a cached HTTP response.
+///
+/// The metadata is kept and expires along with its value, as the two are stored together.
+///
+impl<Key: Hash + Eq + Clone, Value, Meta, S: BuildHasher> TimedCache<Key, (Value, Meta), S> {
+    ///
+    /// Stores the `value` and its `meta`data in the cache for the `key`, resetting the time it
+    /// was stored, and returns the value and metadata previously stored for the `key`.
+    ///
+    pub fn insert_with_meta(
+        &mut self,
+        key: Key,
+        value: Value,
+        meta: Meta,
+    ) -> Option<(Value, Meta)> {
+        self.insert(key, (value, meta))
+    }
+
+    ///
+    /// Retrieves references to the value and metadata stored in the cache for the `key` only if
+    /// they exist and are still considered valid.
+    ///
+    pub fn get_with_meta(&self, key: &Key) -> Option<(&Value, &Meta)> {
+        self.get_if_present(key).map(|(value, meta)| (value, meta))
+    }
+}
+
+///
+/// A cache storing the result of each fallible generation, so that errors are cached too.
+///
+impl<Key: Hash + Eq + Clone, Value, E, S: BuildHasher> TimedCache<Key, Result<Value, E>, S> {
+    ///
+    /// Behaves like `TimedCache::get` with a fallible generator, caching both successes and
+    /// errors: a value is kept for the cache's time to keep, but an error only for `error_ttl`.
+    ///
+    /// While an error is cached, it is returned without calling `generate_value` again, so a
+    /// failing backend is not hammered with retries. Once it expires, the next call generates
+    /// the value again.
+    ///
+    pub fn try_get_caching_errors(
+        &mut self,
+        key: &Key,
+        error_ttl: Duration,
+        generate_value: impl Fn() -> Result<Value, E>,
+    ) -> Result<&Value, E>
+    where
+        E: Clone,
+    {
+        let uncacheable = self
+            .uncacheable
+            .as_ref()
+            .map_or(false, |uncacheable| uncacheable(key));
+        if uncacheable {
+            // Nothing generated for the key is stored, so there is no error to give a time to
+            // live to.
+            return self.get(key, generate_value).as_ref().map_err(E::clone);
+        }
+        if self.get(key, generate_value).is_err() {
+            // This also applies to an error already cached, but that error was given the same
+            // time to live when it was stored, so nothing changes.
+            let _ = self.set_entry_ttl(key, error_ttl);
+        }
+        match self.store.get(key).map(|timed_data| &timed_data.item) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(error)) => Err(error.clone()),
+            None => unreachable!("a cacheable result is always stored by `TimedCache::get`"),
+        }
+    }
+}
+
+///
+/// A cache storing shared values, which may also be held outside the cache.
+///
+impl<Key: Hash + Eq + Clone, Value, S: BuildHasher> TimedCache<Key, Arc<Value>, S> {
+    ///
+    /// Returns how many strong references to the value stored for the `key` are held outside
+    /// the cache, whether or not the value is still valid, or `None` if there is no value stored
+    /// for the `key`.
+    ///
+    /// Evicting a value with no references outside the cache frees it, whereas evicting one
+    /// which is still referenced elsewhere frees nothing until those references are dropped.
+    ///
+    pub fn external_refs(&self, key: &Key) -> Option<usize> {
+        self.store
+            .get(key)
+            .map(|timed_data| Arc::strong_count(&timed_data.item) - 1)
+    }
+}
+
+///
+/// Removes and returns every entry of the `store` for which `pred` returns `true`, as
+/// `HashMap::extract_if` would on newer compilers than this crate supports.
+///
+fn extract_matching<Key: Hash + Eq, Value, S: BuildHasher>(
+    store: &mut HashMap<Key, Value, S>,
+    mut pred: impl FnMut(&Key, &Value) -> bool,
+) -> Vec<(Key, Value)> {
+    let entries: Vec<_> = store.drain().collect();
+    let mut extracted = Vec::new();
+    for (key, value) in entries {
+        if pred(&key, &value) {
+            extracted.push((key, value));
+        } else {
+            let _ = store.insert(key, value);
+        }
+    }
+    extracted
+}
+
+///
+/// Returns the instant which is as far from `new_now` as `instant` is from `old_now`, halving the
+/// distance into the past until it can be represented. A distance into the future which cannot
+/// be represented gives the latest instant which can.
+///
+fn rebase_instant(instant: Instant, old_now: Instant, new_now: Instant) -> Instant {
+    if instant >= old_now {
+        return saturating_add(new_now, instant - old_now);
+    }
+    let mut age = old_now - instant;
+    loop {
+        if let Some(rebased) = new_now.checked_sub(age) {
+            return rebased;
+        }
+        age /= 2;
+    }
+}
+
+///
+/// Returns the index of the `bucket`-sized bucket of time containing `remaining`.
+///
+fn bucket_index(remaining: Duration, bucket: Duration) -> u64 {
+    (remaining.as_nanos() / bucket.as_nanos()).min(u128::from(u64::MAX)) as u64
+}
+
+///
+/// Returns the start of the bucket with the `index`, as returned by `bucket_index`.
+///
+fn bucket_start(index: u64, bucket: Duration) -> Duration {
+    let nanos = u128::from(index).saturating_mul(bucket.as_nanos());
+    let secs = (nanos / 1_000_000_000).min(u128::from(u64::MAX)) as u64;
+    Duration::new(secs, (nanos % 1_000_000_000) as u32)
+}
+
+///
+/// Returns the time to live given by the first of the `ttl_rules` matching the `key`, if any.
+///
+fn rule_ttl<Key>(ttl_rules: &[(KeyPredicate<Key>, Duration)], key: &Key) -> Option<Duration> {
+    ttl_rules
+        .iter()
+        .find(|(predicate, _)| predicate(key))
+        .map(|&(_, ttl)| ttl)
+}
+
+///
+/// Creates the `TimedData` for a newly stored value, giving it the time to live of the rule
+/// matching its key if there is one, jittered if jitter is enabled.
+///
+fn new_timed_data<Value>(
+    jitter: &mut Option<Jitter>,
+    time_to_keep: Duration,
+    rule_ttl: Option<Duration>,
+    value: Value,
+    now: Instant,
+) -> TimedData<Value> {
+    let mut timed_data = TimedData::new_at(value, now);
+    timed_data.time_to_live = rule_ttl;
+    if let Some(ref mut jitter) = *jitter {
+        let time_to_live = rule_ttl.unwrap_or(time_to_keep);
+        timed_data.time_to_live = Some(jitter.next_time_to_live(time_to_live));
+    }
+    timed_data
+}
+
+///
+/// Invokes the eviction callback, if one has been set, for an entry evicted from the cache.
+///
+fn notify_evicted<Key, Value>(
+    on_evict: &mut EvictionNotifier<Key, Value>,
+    key: &Key,
+    value: &Value,
+    reason: EvictionReason,
+) {
+    on_evict.count = on_evict.count.saturating_add(1);
+    if let Some(ref mut callback) = on_evict.callback {
+        callback(key, value, reason);
+    }
+}
+
+///
+/// Two caches are equal if they hold the same valid keys with equal values.
+///
+/// Expired entries, the times values were stored, and all other configuration are left out of
+/// the comparison, so that caches built independently at different times with the same contents
+/// compare equal.
+///
+impl<Key: Hash + Eq, Value: PartialEq, S: BuildHasher> PartialEq for TimedCache<Key, Value, S> {
+    fn eq(&self, other: &TimedCache<Key, Value, S>) -> bool {
+        self.iter().count() == other.iter().count()
+            && self
+                .iter()
+                .all(|(key, value)| other.get_if_present(key) == Some(value))
+    }
+}
+
+///
+/// Creates an empty cache with the time to keep set with `set_global_default_ttl`, or 60 seconds
+/// if none has been set.
+///
+impl<Key: Hash + Eq, Value> Default for TimedCache<Key, Value> {
+    fn default() -> TimedCache<Key, Value> {
+        TimedCache::with_time_to_keep(default_ttl::global_default_ttl())
+    }
+}
+
+impl<Key: Hash + Eq, Value, S> Drop for TimedCache<Key, Value, S> {
+    fn drop(&mut self) {
+        if self.on_evict.callback.is_some() {
+            let on_evict = &mut self.on_evict;
+            self.store.drain().for_each(|(key, timed_data)| {
+                notify_evicted(on_evict, &key, &timed_data.item, EvictionReason::Dropped)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BackoffError, CacheMiss, CacheStatsSnapshot, Clock, EvictionReason, GetOutcome,
+        LocalTimedCache, ManualClock, TimedCache,
+    };
+    use std::cell::Cell;
+    use std::collections::{BTreeMap, HashMap};
+    use std::hash::{BuildHasherDefault, Hash, Hasher};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant, SystemTime};
+    use timed_data::TimedData;
+
+    const KEY: &str = "test";
+
+    struct TestService(usize);
+
+    impl TestService {
+        fn next(&mut self) -> usize {
+            let n = self.0;
+            self.0 += 1;
+            n
+        }
+    }
+
+    ///
+    /// Fills the cache with the keys `0..ages.len()`, each with a value equal to its key which was
+    /// stored the corresponding number of seconds ago in `ages`.
+    ///
+    fn insert_aged(cache: &mut TimedCache<usize, usize>, ages: &[u64]) {
+        let now = Instant::now();
+        ages.iter().enumerate().for_each(|(key, &age)| {
+            let timed_data = TimedData::new_at(key, now - Duration::from_secs(age));
+            cache.store.insert(key, timed_data);
+        });
+    }
+
+    ///
+    /// Records every eviction from the `cache` as a `(key, value, reason)` triple in the vector
+    /// returned.
+    ///
+    fn record_evictions<Key: Hash + Eq + Clone + Send + 'static, Value: Clone + Send + 'static>(
+        cache: &mut TimedCache<Key, Value>,
+    ) -> Arc<Mutex<Vec<(Key, Value, EvictionReason)>>> {
+        let evictions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = evictions.clone();
+        cache.set_on_evict_with_reason(move |key, value, reason| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((key.clone(), value.clone(), reason))
+        });
+        evictions
+    }
+
+    ///
+    /// Stores a value in the cache that was stored exactly `time_to_keep` ago, and so is no
+    /// longer considered valid.
+    ///
+    fn insert_expired<Key: Hash + Eq + Clone, Value>(
+        cache: &mut TimedCache<Key, Value>,
+        key: Key,
+        value: Value,
+    ) {
+        let timed_data = TimedData::new_at(value, Instant::now() - cache.time_to_keep);
+        cache.store.insert(key, timed_data);
+    }
+
+    #[test]
+    fn should_create_test_from_duration() {
+        TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(3));
+    }
+
+    #[test]
+    fn should_contain_same_value_for_key_within_duration() {
+        let service = Mutex::new(TestService(0));
+        let generate_value = || service.lock().unwrap().next();
+
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        let a = *cache.get(&KEY.to_owned(), generate_value);
+        let b = *cache.get(&KEY.to_owned(), generate_value);
+
+        assert_eq!(a, 0);
+        assert_eq!(b, 0);
+    }
+
+    #[test]
+    fn should_contain_different_value_for_key_after_duration() {
+        let service = Mutex::new(TestService(0));
+        let generate_value = || service.lock().unwrap().next();
+
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(5));
+
+        let a = *cache.get(&KEY.to_owned(), generate_value);
+        sleep(Duration::from_millis(5));
+        let b = *cache.get(&KEY.to_owned(), generate_value);
+
+        assert_ne!(a, b);
+        assert_eq!(a, 0);
+        assert_eq!(b, 1);
+    }
+
+    #[test]
+    fn should_compute_value_with_generator_set_on_miss() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_on_miss(|key| key.len());
+
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), None);
+        assert_eq!(*cache.get_or_compute(&KEY.to_owned()), KEY.len());
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&KEY.len()));
+    }
+
+    #[test]
+    #[should_panic(expected = "without a generator")]
+    fn should_panic_computing_value_without_generator_set_on_miss() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get_or_compute(&KEY.to_owned());
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_until_at_target_weight() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        for key in 1..5 {
+            cache.insert(key, key * 10);
+        }
+        cache.get(&1, || unreachable!());
+        let weigh = |_: &usize, value: &usize| *value;
+
+        assert_eq!(cache.evict_to_weight(60, weigh), 2);
+        assert_eq!(cache.keys_sorted().collect::<Vec<_>>(), vec![&1, &4]);
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![
+                (2, 20, EvictionReason::Capacity),
+                (3, 30, EvictionReason::Capacity)
+            ]
+        );
+        assert_eq!(cache.evict_to_weight(50, weigh), 0);
+    }
+
+    #[test]
+    fn should_report_expired_entries_evicted_to_weight_as_expired() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        insert_expired(&mut cache, 5, 50);
+        cache.insert(1, 10);
+
+        assert_eq!(cache.evict_to_weight(10, |_, value| *value), 1);
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(5, 50, EvictionReason::Expired)]
+        );
+    }
+
+    #[test]
+    fn should_weigh_each_entry_once_when_evicting_to_weight() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        for key in 0..3 {
+            cache.insert(key, key);
+        }
+        // A weight which grows each time it is asked for would underflow the total if entries
+        // were weighed again as they were evicted.
+        let weighed = Cell::new(0);
+        let weigh = |_: &usize, _: &usize| {
+            weighed.set(weighed.get() + 1);
+            weighed.get()
+        };
+
+        assert_eq!(cache.evict_to_weight(0, weigh), 3);
+        assert_eq!(weighed.get(), 3);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_estimate_memory_proportional_to_entry_count() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let value_size = |_: &usize| 8;
+
+        assert_eq!(cache.estimated_memory(value_size), 0);
+
+        (0..10).for_each(|key| {
+            cache.get(&key, || key);
+        });
+        let ten_entries = cache.estimated_memory(value_size);
+
+        (10..20).for_each(|key| {
+            cache.get(&key, || key);
+        });
+        let twenty_entries = cache.estimated_memory(value_size);
+
+        assert!(ten_entries > 10 * 8);
+        assert_eq!(twenty_entries, 2 * ten_entries);
+    }
+
+    #[test]
+    fn should_snapshot_only_valid_entries() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&"valid".to_owned(), || 1);
+        insert_expired(&mut cache, "expired".to_owned(), 2);
+
+        let snapshot = cache.valid_snapshot();
+
+        let mut expected = HashMap::new();
+        expected.insert("valid".to_owned(), 1);
+        assert_eq!(snapshot, expected);
+    }
+
+    #[test]
+    fn should_fold_only_valid_entries() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        (1..=4).for_each(|key| {
+            cache.insert(key, key * 10);
+        });
+        insert_expired(&mut cache, 5, 50);
+
+        let total = cache.fold_valid(0, |total, _, value| total + value);
+        let keys = cache.fold_valid(0, |keys, key, _| keys + key);
+
+        assert_eq!(total, 100);
+        assert_eq!(keys, 10);
+    }
+
+    #[test]
+    fn should_evict_remaining_entries_when_dropped() {
+        let evicted = Arc::new(AtomicUsize::new(0));
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let counter = evicted.clone();
+        cache.set_on_evict(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        (0..3).for_each(|key| {
+            cache.get(&key, || key);
+        });
+        insert_expired(&mut cache, 3, 3);
+
+        assert_eq!(evicted.load(Ordering::SeqCst), 0);
+        drop(cache);
+        assert_eq!(evicted.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn should_refresh_all_entries_including_expired() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 1);
+        insert_expired(&mut cache, 2, 2);
+        let stored_before = cache.store[&2].time_stored;
+
+        cache.refresh_all(|key| key * 10, true);
+
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+        assert_eq!(cache.get_if_present(&2), Some(&20));
+        assert!(cache.store[&2].time_stored > stored_before);
+    }
+
+    #[test]
+    fn should_drop_expired_entries_when_refreshing_all_without_refresh_expired() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 1);
+        insert_expired(&mut cache, 2, 2);
+
+        cache.refresh_all(|key| key * 10, false);
+
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+        assert!(!cache.store.contains_key(&2));
+    }
+
+    #[test]
+    fn should_not_allocate_before_first_insert() {
+        let caches: Vec<_> = (0..1000)
+            .map(|_| TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10)))
+            .collect();
+        assert!(caches.iter().all(|cache| cache.store.capacity() == 0));
+
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 0);
+        assert!(cache.store.capacity() > 0);
+    }
+
+    #[test]
+    fn should_return_previous_value_on_insert() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        assert_eq!(cache.insert(KEY.to_owned(), 1), None);
+        assert_eq!(cache.insert(KEY.to_owned(), 2), Some(1));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+    }
+
+    #[test]
+    fn should_get_value_written_through() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+
+        assert_eq!(*cache.write_through(KEY.to_owned(), 2), 2);
+        assert_eq!(*cache.get(&KEY.to_owned(), || 3), 2);
+    }
+
+    #[test]
+    fn should_count_hits_and_misses_per_key() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.key_stats(&KEY.to_owned()), None);
+
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+        insert_expired(&mut cache, KEY.to_owned(), 0);
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+
+        assert_eq!(cache.key_stats(&KEY.to_owned()), Some((3, 2)));
+    }
+
+    #[test]
+    fn should_catch_generator_panic_leaving_entry_untouched() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+
+        let result = cache.try_get_catching(&KEY.to_owned(), || panic!("malformed input"));
+
+        let payload = result.err().unwrap();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"malformed input"));
+        assert_eq!(cache.store[KEY].item, 1);
+        assert_eq!(cache.try_get_catching(&KEY.to_owned(), || 2).ok(), Some(&2));
+    }
+
+    #[test]
+    fn should_report_age_and_expiry_of_valid_entry() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 0);
+
+        assert!(cache.age(&KEY.to_owned()).unwrap() < Duration::from_secs(10));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(false));
+    }
+
+    #[test]
+    fn should_report_age_and_expiry_of_expired_entry() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 0);
+
+        assert!(cache.age(&KEY.to_owned()).unwrap() >= Duration::from_secs(10));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+    }
+
+    #[test]
+    fn should_attach_typed_metadata_to_stored_value() {
+        #[derive(Debug, PartialEq)]
+        struct Source(&'static str);
+
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(!cache.set_metadata(&1, Source("absent")));
+        cache.insert(1, 10);
+
+        assert!(cache.set_metadata(&1, Source("https://example.com")));
+        assert_eq!(cache.metadata(&1), Some(&Source("https://example.com")));
+        assert_eq!(cache.metadata::<u32>(&1), None);
+
+        cache.insert(1, 11);
+        assert_eq!(cache.metadata::<Source>(&1), None);
+    }
+
+    #[test]
+    fn should_count_references_held_outside_cache() {
+        let mut cache = TimedCache::<usize, Arc<usize>>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.external_refs(&1), None);
+        cache.insert(1, Arc::new(10));
+        assert_eq!(cache.external_refs(&1), Some(0));
+
+        let first = cache.get(&1, || unreachable!()).clone();
+        let second = first.clone();
+        assert_eq!(cache.external_refs(&1), Some(2));
+
+        drop(first);
+        assert_eq!(cache.external_refs(&1), Some(1));
+        drop(second);
+        assert_eq!(cache.external_refs(&1), Some(0));
+    }
+
+    #[test]
+    fn should_report_remaining_ttl_or_zero() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 1);
+        clock.advance(Duration::from_secs(4));
+
+        assert_eq!(cache.remaining_ttl_or_zero(&1), Duration::from_secs(6));
+        assert_eq!(cache.remaining_ttl_or_zero(&2), Duration::from_secs(0));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.remaining_ttl_or_zero(&1), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn should_report_no_age_or_expiry_of_absent_entry() {
+        let cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        assert_eq!(cache.age(&KEY.to_owned()), None);
+        assert_eq!(cache.is_expired(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_purge_expired_entries() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 1);
+        insert_expired(&mut cache, 2, 2);
+        insert_expired(&mut cache, 3, 3);
+
+        assert_eq!(cache.purge_expired(), 2);
+        assert_eq!(cache.valid_snapshot().len(), 1);
+        assert_eq!(cache.store.len(), 1);
+    }
+
+    #[test]
+    fn should_retain_only_valid_entries_matching_predicate() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 1);
+        cache.get(&2, || 2);
+        insert_expired(&mut cache, 3, 3);
+        insert_expired(&mut cache, 4, 4);
+
+        cache.retain_valid(|_, &value| value % 2 == 0);
+
+        let keys: Vec<_> = cache.store.keys().cloned().collect();
+        assert_eq!(keys, vec![2]);
+    }
+
+    #[test]
+    fn should_require_valid_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+
+        assert_eq!(cache.require(&KEY.to_owned()), Ok(&1));
+    }
+
+    #[test]
+    fn should_fail_to_require_expired_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+
+        assert_eq!(cache.require(&KEY.to_owned()), Err(CacheMiss::Expired));
+    }
+
+    #[test]
+    fn should_fail_to_require_absent_value() {
+        let cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        assert_eq!(cache.require(&KEY.to_owned()), Err(CacheMiss::Absent));
+    }
+
+    #[test]
+    fn should_extend_entry_ttl() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+
+        assert!(cache.set_entry_ttl(&KEY.to_owned(), Duration::from_secs(20)));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&1));
+    }
+
+    #[test]
+    fn should_shorten_entry_ttl() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+
+        assert!(cache.set_entry_ttl(&KEY.to_owned(), Duration::from_millis(5)));
+        sleep(Duration::from_millis(5));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_not_set_entry_ttl_of_absent_key() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        assert!(!cache.set_entry_ttl(&KEY.to_owned(), Duration::from_secs(20)));
+    }
+
+    ///
+    /// A trivial FNV-1a hasher, to check the cache works with hashers other than the default.
+    ///
+    struct FnvHasher(u64);
+
+    impl Default for FnvHasher {
+        fn default() -> FnvHasher {
+            FnvHasher(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl Hasher for FnvHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 ^= u64::from(*byte);
+                self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+    }
+
+    #[test]
+    fn should_cache_values_with_custom_hasher() {
+        let service = Mutex::new(TestService(0));
+        let generate_value = || service.lock().unwrap().next();
+
+        let mut cache = TimedCache::<String, usize, _>::with_hasher(
+            Duration::from_secs(10),
+            BuildHasherDefault::<FnvHasher>::default(),
+        );
+
+        assert_eq!(*cache.get(&KEY.to_owned(), generate_value), 0);
+        assert_eq!(*cache.get(&KEY.to_owned(), generate_value), 0);
+        assert_eq!(*cache.get(&"other".to_owned(), generate_value), 1);
+        assert_eq!(cache.key_stats(&KEY.to_owned()), Some((1, 1)));
+    }
+
+    #[test]
+    fn should_group_keys_by_expiry_bucket() {
+        let mut cache = TimedCache::<&str, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&"ten", || 0);
+        cache.get(&"five", || 0);
+        cache.set_entry_ttl(&"five", Duration::from_secs(5));
+        cache.get(&"two", || 0);
+        cache.set_entry_ttl(&"two", Duration::from_secs(2));
+        insert_expired(&mut cache, "expired", 0);
+
+        let groups = cache.group_by_expiry_bucket(Duration::from_secs(3));
+
+        let mut expected = BTreeMap::new();
+        expected.insert(0, vec!["two"]);
+        expected.insert(1, vec!["five"]);
+        expected.insert(3, vec!["ten"]);
+        assert_eq!(groups, expected);
+    }
+
+    #[test]
+    fn should_overwrite_value_keeping_its_time_stored() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(KEY.to_owned(), 1);
+        clock.advance(Duration::from_secs(4));
+        let histogram = cache.remaining_ttl_histogram(Duration::from_secs(1));
+
+        assert!(cache.overwrite_value(&KEY.to_owned(), 2));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+        assert_eq!(cache.age(&KEY.to_owned()), Some(Duration::from_secs(4)));
+        assert_eq!(
+            cache.remaining_ttl_histogram(Duration::from_secs(1)),
+            histogram
+        );
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_not_overwrite_missing_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(!cache.overwrite_value(&KEY.to_owned(), 2));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_swap_valid_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+
+        assert_eq!(cache.swap(&KEY.to_owned(), 2), Some(1));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+    }
+
+    #[test]
+    fn should_insert_without_returning_expired_or_absent_value_on_swap() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+
+        assert_eq!(cache.swap(&KEY.to_owned(), 2), None);
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+        assert_eq!(cache.swap(&"other".to_owned(), 3), None);
+        assert_eq!(cache.get_if_present(&"other".to_owned()), Some(&3));
+    }
+
+    #[test]
+    fn should_stagger_expiry_of_values_stored_together_with_jitter() {
+        let time_to_keep = Duration::from_secs(10);
+        let max_jitter = Duration::from_secs(10);
+        let mut cache =
+            TimedCache::<usize, usize>::with_jittered_sliding_window(time_to_keep, max_jitter, 1);
+        (0..20).for_each(|key| {
+            cache.get(&key, || key);
+        });
+
+        let time_to_lives: Vec<_> = cache
+            .store
+            .values()
+            .map(|timed_data| timed_data.time_to_live.unwrap())
+            .collect();
+        assert!(time_to_lives
+            .iter()
+            .all(|&ttl| ttl >= time_to_keep && ttl < time_to_keep + max_jitter));
+
+        let halfway = cache.store[&0].time_stored + time_to_keep + max_jitter / 2;
+        let still_valid = cache
+            .store
+            .values()
+            .filter(|timed_data| timed_data.still_valid_at(time_to_keep, halfway))
+            .count();
+        assert!(still_valid > 0 && still_valid < 20);
+    }
+
+    #[test]
+    fn should_slide_window_when_valid_value_retrieved() {
+        let time_to_keep = Duration::from_secs(10);
+        let mut cache = TimedCache::<String, usize>::with_jittered_sliding_window(
+            time_to_keep,
+            Duration::from_secs(1),
+            1,
+        );
+        cache.get(&KEY.to_owned(), || 1);
+        let time_to_live = cache.store[KEY].time_to_live;
+        cache.store.get_mut(KEY).unwrap().time_stored -= Duration::from_secs(9);
+
+        assert_eq!(*cache.get(&KEY.to_owned(), || 2), 1);
+        assert!(cache.age(&KEY.to_owned()).unwrap() < Duration::from_secs(1));
+        assert_eq!(cache.store[KEY].time_to_live, time_to_live);
+    }
+
+    #[test]
+    fn should_not_slide_window_by_default() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+        cache.store.get_mut(KEY).unwrap().time_stored -= Duration::from_secs(9);
+
+        cache.get(&KEY.to_owned(), || 2);
+        assert!(cache.age(&KEY.to_owned()).unwrap() >= Duration::from_secs(9));
+    }
+
+    #[test]
+    fn should_extend_life_of_valid_entries_only() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 1);
+        clock.advance(Duration::from_secs(9));
+        cache.insert(2, 2);
+        cache.set_entry_ttl(&2, Duration::from_secs(0));
+
+        cache.extend_all(Duration::from_secs(300));
+        cache.insert(3, 3);
+
+        clock.advance(Duration::from_secs(200));
+        assert_eq!(cache.get_if_present(&1), Some(&1));
+        assert_eq!(cache.get_if_present(&2), None);
+        assert_eq!(cache.get_if_present(&3), None);
+        clock.advance(Duration::from_secs(101));
+        assert_eq!(cache.get_if_present(&1), None);
+    }
+
+    #[test]
+    fn should_drain_expired_entries_into_reused_buffer() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        clock.advance(Duration::from_secs(5));
+        cache.insert(3, 30);
+        clock.advance(Duration::from_secs(5));
+        let mut drained = Vec::with_capacity(4);
+
+        assert_eq!(cache.drain_expired_into(&mut drained), 2);
+        drained.sort();
+        assert_eq!(drained, vec![(1, 10), (2, 20)]);
+
+        drained.clear();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.drain_expired_into(&mut drained), 1);
+        assert_eq!(drained, vec![(3, 30)]);
+        assert!(drained.capacity() >= 4);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_extract_matching_entries_regardless_of_validity() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        (0..4).for_each(|key| {
+            cache.get(&key, || key * 10);
+        });
+        insert_expired(&mut cache, 4, 40);
+
+        let mut extracted = cache.extract_if(|key, _| key % 2 == 0);
+        extracted.sort();
+
+        assert_eq!(extracted, vec![(0, 0), (2, 20), (4, 40)]);
+        let mut remaining: Vec<_> = cache.valid_snapshot().into_iter().collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![(1, 10), (3, 30)]);
+    }
+
+    #[test]
+    fn should_hold_peeked_reference_while_calling_len() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(cache.is_empty());
+
+        cache.ensure(&KEY.to_owned(), || 1);
+        cache.ensure(&KEY.to_owned(), || 2);
+
+        let value = cache.peek(&KEY.to_owned()).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.is_empty());
+        assert_eq!(*value, 1);
+    }
+
+    #[test]
+    fn should_find_oldest_and_newest_entries_without_removing_them() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.oldest(), None);
+        assert_eq!(cache.newest(), None);
+        insert_aged(&mut cache, &[3, 20, 1]);
+
+        assert_eq!(cache.oldest(), Some((&1, &1)));
+        assert_eq!(cache.newest(), Some((&2, &2)));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn should_evict_entries_stored_at_same_instant_in_store_order() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_clock(ManualClock::new());
+        cache.set_capacity(3);
+        let evictions = record_evictions(&mut cache);
+        for key in &[5, 3, 8, 1] {
+            cache.insert(*key, *key);
+        }
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(5, 5, EvictionReason::Capacity)]
+        );
+        assert_eq!(cache.newest(), Some((&1, &1)));
+        assert_eq!(cache.evict_oldest(), Some((3, 3)));
+        assert_eq!(cache.evict_oldest(), Some((8, 8)));
+        assert_eq!(cache.evict_oldest(), Some((1, 1)));
+    }
+
+    #[test]
+    fn should_evict_oldest_entry() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[3, 20, 1]);
+
+        assert_eq!(cache.evict_oldest(), Some((1, 1)));
+        assert_eq!(cache.evict_oldest(), Some((0, 0)));
+        assert_eq!(cache.evict_oldest(), Some((2, 2)));
+        assert_eq!(cache.evict_oldest(), None);
+    }
+
+    #[test]
+    fn should_evict_newest_entry() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[3, 20, 1]);
+
+        assert_eq!(cache.evict_newest(), Some((2, 2)));
+        assert_eq!(cache.evict_newest(), Some((0, 0)));
+        assert_eq!(cache.evict_newest(), Some((1, 1)));
+        assert_eq!(cache.evict_newest(), None);
+    }
+
+    #[test]
+    fn should_report_small_time_since_regeneration() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.time_since_regeneration(&KEY.to_owned()), None);
+
+        insert_expired(&mut cache, KEY.to_owned(), 0);
+        assert!(cache.time_since_regeneration(&KEY.to_owned()).unwrap() >= Duration::from_secs(10));
+
+        cache.get(&KEY.to_owned(), || 1);
+        assert!(cache.time_since_regeneration(&KEY.to_owned()).unwrap() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_not_reset_time_since_regeneration_on_sliding_hit() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.sliding = true;
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.get(&KEY.to_owned(), || 1);
+        clock.advance(Duration::from_secs(6));
+
+        cache.get(&KEY.to_owned(), || unreachable!());
+        clock.advance(Duration::from_secs(6));
+
+        assert_eq!(cache.age(&KEY.to_owned()), Some(Duration::from_secs(6)));
+        assert_eq!(
+            cache.time_since_regeneration(&KEY.to_owned()),
+            Some(Duration::from_secs(12))
+        );
+    }
+
+    #[test]
+    fn should_remove_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 1);
+
+        assert_eq!(cache.remove(&KEY.to_owned()), Some(1));
+        assert_eq!(cache.remove(&KEY.to_owned()), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_remove_many_values_in_order() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 10);
+        insert_expired(&mut cache, 3, 30);
+
+        assert_eq!(
+            cache.remove_many(&[3, 2, 1]),
+            vec![Some(30), None, Some(10)]
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_validate_cache_built_through_public_methods() {
+        let mut cache = TimedCache::<usize, usize>::with_jittered_sliding_window(
+            Duration::from_secs(10),
+            Duration::from_secs(1),
+            1,
+        );
+        cache.get(&1, || 1);
+        cache.insert(2, 2);
+        cache.write_through(3, 3);
+        cache.swap(&4, 4);
+        cache.set_entry_ttl(&1, Duration::from_secs(1));
+        cache.refresh_all(|key| *key, false);
+
+        cache.debug_validate();
+    }
+
+    #[test]
+    fn should_validate_entry_stored_in_future() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert_with_timestamp(1, 1, clock.now() + Duration::from_secs(60));
+
+        cache.debug_validate();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "more entries than its capacity")]
+    fn should_fail_to_validate_cache_over_capacity() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.capacity = Some(1);
+
+        cache.debug_validate();
+    }
+
+    #[test]
+    fn should_prewarm_only_missing_or_expired_keys() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 1);
+        insert_expired(&mut cache, 2, 2);
+
+        let generated = cache.prewarm(&[1, 2, 3], |key| key * 10);
+
+        assert_eq!(generated, 2);
+        assert_eq!(cache.get_if_present(&1), Some(&1));
+        assert_eq!(cache.get_if_present(&2), Some(&20));
+        assert_eq!(cache.get_if_present(&3), Some(&30));
+    }
+
+    #[test]
+    fn should_map_keys_preserving_values_and_timestamps() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct UserId(u32);
+
+        let mut cache = TimedCache::<u32, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 10);
+        insert_expired(&mut cache, 2, 20);
+        let stored = cache.store[&1].time_stored;
+
+        let cache = cache.map_keys(UserId);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_if_present(&UserId(1)), Some(&10));
+        assert_eq!(cache.store[&UserId(1)].time_stored, stored);
+        assert_eq!(cache.is_expired(&UserId(2)), Some(true));
+    }
+
+    #[test]
+    fn should_find_next_expiry_of_oldest_entry() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.next_expiry(), None);
+        insert_aged(&mut cache, &[3, 5, 1]);
+
+        let oldest = cache.store[&1].time_stored;
+        assert_eq!(cache.next_expiry(), Some(oldest + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn should_list_valid_entries_in_order_of_expiry() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(cache.expiry_timeline().is_empty());
+        insert_aged(&mut cache, &[3, 5, 1, 12]);
+
+        let timeline = cache.expiry_timeline();
+        let keys: Vec<usize> = timeline.iter().map(|(_, key)| *key).collect();
+        assert_eq!(keys, vec![1, 0, 2]);
+        assert!(timeline.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(
+            timeline[0].0,
+            cache.store[&1].time_stored + Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn should_rebuild_cache_from_parts() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&1, || 10);
+        insert_expired(&mut cache, 2, 20);
+
+        let (time_to_keep, values) = cache.into_parts();
+        assert_eq!(time_to_keep, Duration::from_secs(10));
+
+        let mut rebuilt = TimedCache::<usize, usize>::with_time_to_keep(time_to_keep);
+        values.into_iter().for_each(|(key, value)| {
+            rebuilt.insert(key, value);
+        });
+        assert_eq!(rebuilt.len(), 1);
+        assert_eq!(rebuilt.get_if_present(&1), Some(&10));
+    }
+
+    #[test]
+    fn should_report_overwritten_eviction_reason() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        cache.insert(1, 10);
+        cache.insert(1, 11);
+        cache.write_through(1, 12);
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![
+                (1, 10, EvictionReason::Overwritten),
+                (1, 11, EvictionReason::Overwritten)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_removed_eviction_reason() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.remove(&1);
+        cache.retain_valid(|_, _| false);
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![
+                (1, 10, EvictionReason::Removed),
+                (2, 20, EvictionReason::Removed)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_expired_eviction_reason() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        insert_expired(&mut cache, 1, 10);
+        cache.purge_expired();
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(1, 10, EvictionReason::Expired)]
+        );
+    }
+
+    #[test]
+    fn should_report_expired_value_replaced_on_regeneration() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        insert_expired(&mut cache, 1, 10);
+        insert_expired(&mut cache, 2, 20);
+
+        assert_eq!(cache.get(&1, || 11), &11);
+        assert_eq!(cache.get_owned_key(2, || 21), &21);
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![
+                (1, 10, EvictionReason::Expired),
+                (2, 20, EvictionReason::Expired)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_dropped_eviction_reason() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        cache.insert(1, 10);
+        drop(cache);
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(1, 10, EvictionReason::Dropped)]
+        );
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_entries_when_lowering_capacity() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(4);
+        let evictions = record_evictions(&mut cache);
+        for key in 0..4 {
+            cache.insert(key, key);
+        }
+        cache.get(&0, || unreachable!());
+        cache.get(&1, || unreachable!());
+
+        cache.set_capacity(2);
+
+        let mut evicted = evictions.lock().unwrap().clone();
+        evicted.sort_by_key(|&(key, _, _)| key);
+        assert_eq!(
+            evicted,
+            vec![
+                (2, 2, EvictionReason::Capacity),
+                (3, 3, EvictionReason::Capacity)
+            ]
+        );
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&0), Some(&0));
+        assert_eq!(cache.peek(&1), Some(&1));
+    }
+
+    #[test]
+    fn should_evict_expired_entry_before_least_recently_used_one() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_capacity(3);
+        let evictions = record_evictions(&mut cache);
+        for key in 0..3 {
+            cache.insert(key, key);
+        }
+        cache.set_entry_ttl(&2, Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+
+        cache.insert(3, 3);
+
+        assert_eq!(
+            *evictions.lock().unwrap(),
+            vec![(2, 2, EvictionReason::Expired)]
+        );
+        assert_eq!(cache.keys_sorted().collect::<Vec<_>>(), vec![&0, &1, &3]);
+    }
+
+    #[test]
+    fn should_store_keys_which_are_not_clone() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Opaque(usize);
+
+        let mut cache = TimedCache::<Opaque, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(2);
+        for key in 0..3 {
+            cache.insert(Opaque(key), key);
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_if_present(&Opaque(0)), None);
+        assert_eq!(cache.peek(&Opaque(1)), Some(&1));
+        assert_eq!(cache.remove(&Opaque(2)), Some(2));
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&Opaque(1), &1)]);
+        assert_eq!(cache.purge_expired(), 0);
+    }
+
+    #[test]
+    fn should_inspect_and_expire_keys_which_are_not_clone() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Opaque(usize);
+
+        let mut cache = TimedCache::<Opaque, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        for key in 0..4 {
+            cache.insert(Opaque(key), key);
+        }
+        assert!(cache.set_entry_ttl(&Opaque(0), Duration::from_secs(1)));
+        assert!(cache.set_metadata(&Opaque(1), "etag"));
+
+        assert_eq!(cache.require(&Opaque(1)), Ok(&1));
+        assert_eq!(cache.get_any(&Opaque(1)), Some((&1, true)));
+        assert_eq!(cache.age(&Opaque(1)), Some(Duration::from_secs(0)));
+        assert_eq!(cache.metadata::<&str>(&Opaque(1)), Some(&"etag"));
+        assert!(cache.generation_id(&Opaque(1)).is_some());
+        assert!(cache.fingerprint(&Opaque(1)).is_some());
+        assert!(cache.contains_all(&[Opaque(1), Opaque(2)]));
+        assert!(cache.contains_any(&[Opaque(9), Opaque(2)]));
+        assert_eq!(cache.key_stats(&Opaque(1)), None);
+        assert_eq!(cache.oldest(), Some((&Opaque(0), &0)));
+        assert_eq!(cache.newest(), Some((&Opaque(3), &3)));
+        assert_eq!(
+            cache.next_expiry(),
+            Some(clock.now() + Duration::from_secs(1))
+        );
+        assert_eq!(
+            cache.age_histogram(Duration::from_secs(1)),
+            vec![(Duration::from_secs(0), 4)]
+        );
+        cache.values_mut().for_each(|value| *value *= 10);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.is_expired(&Opaque(0)), Some(true));
+        assert_eq!(
+            cache.remaining_ttl_or_zero(&Opaque(0)),
+            Duration::from_secs(0)
+        );
+        let mut expired = 0;
+        cache.for_each_expired(|_, _| expired += 1);
+        assert_eq!(expired, 1);
+        let mut drained = Vec::new();
+        assert_eq!(cache.drain_expired_into(&mut drained), 1);
+        assert_eq!(drained, vec![(Opaque(0), 0)]);
+        assert_eq!(cache.take_valid(&Opaque(1)), Some(10));
+        assert_eq!(cache.extract_if(|key, _| key.0 == 2), vec![(Opaque(2), 20)]);
+        assert_eq!(cache.retain_newest(0), 1);
+
+        let (time_to_keep, entries) = cache.into_parts();
+        assert_eq!(time_to_keep, Duration::from_secs(10));
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn should_view_compare_and_rekey_keys_which_are_not_clone() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Opaque(usize);
+
+        let mut cache = TimedCache::<Opaque, usize>::with_time_to_keep(Duration::from_secs(10));
+        let mut other = TimedCache::<Opaque, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(Opaque(1), 1);
+        other.insert(Opaque(1), 1);
+        assert!(cache == other);
+
+        let value = cache.entry(Opaque(1)).and_modify(|value| *value += 1);
+        assert_eq!(value.key(), &Opaque(1));
+        assert_eq!(cache.read_view().get_if_present(&Opaque(1)), Some(&2));
+
+        let rekeyed = cache.map_keys(|Opaque(key)| Opaque(key * 10));
+        assert_eq!(rekeyed.get_if_present(&Opaque(10)), Some(&2));
+
+        let local = LocalTimedCache::<Opaque, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(local.get_if_present(&Opaque(1)).is_none());
+    }
+
+    #[test]
+    fn should_report_pressure_when_full_cache_evicts() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(2);
+        let pressure = Arc::new(Mutex::new(Vec::new()));
+        let recorded = pressure.clone();
+        cache.set_on_pressure(move |len, capacity| recorded.lock().unwrap().push((len, capacity)));
+
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        cache.insert(1, 10);
+        assert!(pressure.lock().unwrap().is_empty());
+
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+        assert_eq!(*pressure.lock().unwrap(), vec![(2, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn should_generate_with_stateful_generator() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let backends = [10, 20, 30];
+        let mut next_backend = 0;
+        let mut round_robin = || {
+            let backend = backends[next_backend % backends.len()];
+            next_backend += 1;
+            backend
+        };
+
+        assert_eq!(*cache.get_mut_gen(&0, &mut round_robin), 10);
+        assert_eq!(*cache.get_mut_gen(&1, &mut round_robin), 20);
+        assert_eq!(*cache.get_mut_gen(&0, &mut round_robin), 10);
+        insert_expired(&mut cache, 0, 0);
+        assert_eq!(*cache.get_mut_gen(&0, &mut round_robin), 30);
+        assert_eq!(next_backend, 3);
+    }
+
+    #[test]
+    fn should_never_hold_more_than_capacity() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(3);
+        for key in 0..20 {
+            match key % 4 {
+                0 => {
+                    cache.insert(key, key);
+                }
+                1 => {
+                    cache.get(&key, || key);
+                }
+                2 => {
+                    cache.swap(&key, key);
+                }
+                _ => {
+                    cache.write_through(key, key);
+                }
+            }
+            assert!(cache.len() <= 3);
+            cache.debug_validate();
+        }
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_entry_when_storing_in_full_cache() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(2);
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        cache.get(&0, || unreachable!());
+        cache.insert(2, 2);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.peek(&0), Some(&0));
+        assert_eq!(cache.peek(&2), Some(&2));
+    }
+
+    #[test]
+    fn should_keep_stats_when_cleared() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+
+        cache.clear();
+
+        assert!(cache.is_empty());
+        assert_eq!(cache.key_stats(&KEY.to_owned()), Some((1, 1)));
+    }
+
+    #[test]
+    fn should_zero_stats_when_reset() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+
+        cache.reset();
+
+        assert!(cache.is_empty());
+        assert_eq!(cache.key_stats(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_get_any_fresh_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(KEY.to_owned(), 1);
+        assert_eq!(cache.get_any(&KEY.to_owned()), Some((&1, true)));
+    }
+
+    #[test]
+    fn should_get_any_stale_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+        assert_eq!(cache.get_any(&KEY.to_owned()), Some((&1, false)));
+    }
+
+    #[test]
+    fn should_not_get_any_absent_value() {
+        let cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.get_any(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_never_expire_entries_kept_for_max_duration() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::MAX);
+        cache.insert(0, 0);
+        cache.get(&1, || 1);
+
+        assert_eq!(cache.is_expired(&0), Some(false));
+        assert_eq!(cache.get(&1, || unreachable!()), &1);
+        assert_eq!(cache.next_expiry(), None);
+        assert_eq!(cache.purge_expired(), 0);
+        assert_eq!(
+            cache.group_by_expiry_bucket(Duration::from_nanos(1)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn should_visit_expired_entries_without_removing_them() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 20, 30]);
+
+        let mut expired = 0;
+        cache.for_each_expired(|_, _| expired += 1);
+
+        assert_eq!(expired, 2);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn should_store_and_retrieve_values_with_metadata() {
+        let mut cache =
+            TimedCache::<String, (usize, String)>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert_with_meta(KEY.to_owned(), 1, "etag-1".to_owned());
+
+        assert_eq!(
+            cache.get_with_meta(&KEY.to_owned()),
+            Some((&1, &"etag-1".to_owned()))
+        );
+        assert_eq!(cache.get_with_meta(&"other".to_owned()), None);
+    }
+
+    #[test]
+    fn should_not_retrieve_expired_values_with_metadata() {
+        let mut cache =
+            TimedCache::<String, (usize, String)>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), (1, "etag-1".to_owned()));
+
+        assert_eq!(cache.get_with_meta(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_iterate_valid_entries_in_sorted_key_order() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        for key in &[5, 3, 9, 1, 7] {
+            cache.insert(*key, key * 10);
+        }
+        insert_expired(&mut cache, 4, 40);
+
+        assert_eq!(
+            cache.iter_sorted().collect::<Vec<_>>(),
+            vec![(&1, &10), (&3, &30), (&5, &50), (&7, &70), (&9, &90)]
+        );
+        assert_eq!(
+            cache.keys_sorted().collect::<Vec<_>>(),
+            vec![&1, &3, &5, &7, &9]
+        );
+        assert_eq!(
+            cache
+                .sorted_valid_snapshot()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(1, 10), (3, 30), (5, 50), (7, 70), (9, 90)]
+        );
+    }
+
+    #[test]
+    fn should_report_how_value_was_retrieved() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_regeneration_cooldown(Duration::from_secs(15));
+        let key = KEY.to_owned();
+
+        assert_eq!(cache.get_detailed(&key, || 1), (&1, GetOutcome::Generated));
+        assert_eq!(cache.get_detailed(&key, || 2), (&1, GetOutcome::Hit));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            cache.get_detailed(&key, || 2),
+            (&1, GetOutcome::ServedStale)
+        );
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get_detailed(&key, || 2), (&2, GetOutcome::Generated));
+    }
+
+    #[test]
+    fn should_keep_previous_value_when_generated_value_is_invalid() {
+        let mut cache = TimedCache::<String, i32>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_validator(|value| *value >= 0);
+        let key = KEY.to_owned();
+        cache.get(&key, || 1);
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(cache.get(&key, || -1), &1);
+        assert_eq!(cache.try_get(&key, || Ok::<_, ()>(-2)), Ok(&1));
+        assert_eq!(cache.validation_failures(), 2);
+        assert_eq!(cache.get_if_present(&key), None);
+
+        assert_eq!(cache.get(&key, || 2), &2);
+        assert_eq!(cache.get_if_present(&key), Some(&2));
+        assert_eq!(cache.validation_failures(), 2);
+    }
+
+    #[test]
+    fn should_not_regenerate_expired_value_within_cooldown() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(10));
+        cache.set_regeneration_cooldown(Duration::from_millis(100));
+        let generations = AtomicUsize::new(0);
+        let generate = || generations.fetch_add(1, Ordering::SeqCst);
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(150) {
+            cache.get(&KEY.to_owned(), generate);
+            sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(generations.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_retry_failing_generator_until_it_succeeds() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let attempts = AtomicUsize::new(0);
+        let generate = || match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Err("flaky"),
+            attempt => Ok(attempt),
+        };
+
+        assert_eq!(
+            cache.try_get_with_retries(&KEY.to_owned(), generate, 2),
+            Ok(&2)
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+    }
+
+    #[test]
+    fn should_return_last_error_without_caching_after_exhausting_retries() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let attempts = AtomicUsize::new(0);
+        let generate = || Err::<usize, _>(attempts.fetch_add(1, Ordering::SeqCst));
+
+        assert_eq!(
+            cache.try_get_with_retries(&KEY.to_owned(), generate, 2),
+            Err(2)
+        );
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_map_only_expired_values_keeping_timestamps() {
+        let mut cache =
+            TimedCache::<usize, Option<String>>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, Some("fresh".to_owned()));
+        insert_expired(&mut cache, 2, Some("stale".to_owned()));
+        let stored = cache.store[&2].time_stored;
+
+        cache.map_expired_values(|_| None);
+
+        assert_eq!(cache.get_any(&1), Some((&Some("fresh".to_owned()), true)));
+        assert_eq!(cache.get_any(&2), Some((&None, false)));
+        assert_eq!(cache.store[&2].time_stored, stored);
+    }
+
+    #[test]
+    fn should_update_values_in_place_keeping_timestamps() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 5, 20]);
+
+        cache.values_mut().for_each(|value| *value *= 2);
+
+        assert_eq!(cache.get_any(&1), Some((&2, true)));
+        assert_eq!(cache.get_any(&2), Some((&4, false)));
+        assert!(cache.age(&1).unwrap() >= Duration::from_secs(5));
+        assert!(cache.age(&2).unwrap() >= Duration::from_secs(20));
+    }
+
+    #[test]
+    fn should_create_equivalent_caches_from_seconds_and_milliseconds() {
+        let from_duration =
+            TimedCache::<String, usize>::with_time_to_keep(Duration::from_millis(1500));
+        let from_secs = TimedCache::<String, usize>::with_ttl_secs(2);
+        let from_millis = TimedCache::<String, usize>::with_ttl_millis(1500);
+
+        assert_eq!(from_secs.time_to_keep, Duration::from_secs(2));
+        assert_eq!(from_millis.time_to_keep, from_duration.time_to_keep);
+    }
+
+    #[test]
+    fn should_compact_mostly_expired_cache() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let mut ages = vec![20; 1000];
+        ages[0] = 0;
+        insert_aged(&mut cache, &ages);
+        let capacity_before = cache.store.capacity();
+
+        assert_eq!(cache.compact(), 999);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.peek(&0), Some(&0));
+        assert!(cache.store.capacity() < capacity_before);
+    }
+
+    ///
+    /// A key which counts how many times it, or any of its clones, has been cloned.
+    ///
+    struct CloneCountingKey(usize, Arc<AtomicUsize>);
+
+    impl Hash for CloneCountingKey {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.hash(state)
+        }
+    }
+
+    impl PartialEq for CloneCountingKey {
+        fn eq(&self, other: &CloneCountingKey) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for CloneCountingKey {}
+
+    impl Clone for CloneCountingKey {
+        fn clone(&self) -> CloneCountingKey {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            CloneCountingKey(self.0, self.1.clone())
+        }
+    }
+
+    #[test]
+    fn should_keep_expired_value_when_try_get_fails() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        insert_expired(&mut cache, 1, 10);
+        let stored = cache.store[&1].time_stored;
+
+        assert_eq!(
+            cache.try_get(&1, || Err::<usize, _>("unavailable")),
+            Err("unavailable")
+        );
+        assert_eq!(cache.get_any(&1), Some((&10, false)));
+        assert_eq!(cache.store[&1].time_stored, stored);
+        assert!(evictions.lock().unwrap().is_empty());
+
+        assert_eq!(cache.try_get(&1, || Ok::<_, &str>(11)), Ok(&11));
+    }
+
+    #[test]
+    fn should_pass_context_to_generator_on_miss() {
+        let mut cache = TimedCache::<usize, String>::with_time_to_keep(Duration::from_secs(10));
+        let token = "token-a".to_owned();
+
+        let value = cache.get_ctx(&1, &token, |token| format!("{}:1", token));
+        assert_eq!(value, "token-a:1");
+
+        let value = cache.get_ctx(&1, "token-b", |_| unreachable!());
+        assert_eq!(value, "token-a:1");
+    }
+
+    #[test]
+    fn should_smart_get_valid_value_without_generating() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_serve_stale_on_error(true);
+        cache.insert(1, 10);
+        assert_eq!(
+            cache.smart_get(&1, || -> Result<usize, ()> { unreachable!() }),
+            Ok(&10)
+        );
+    }
+
+    #[test]
+    fn should_smart_get_fresh_value_when_generation_succeeds() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_serve_stale_on_error(true);
+        insert_expired(&mut cache, 1, 10);
+        assert_eq!(cache.smart_get(&1, || Ok::<_, ()>(11)), Ok(&11));
+        assert_eq!(cache.get_if_present(&1), Some(&11));
+    }
+
+    #[test]
+    fn should_smart_get_stale_value_when_generation_fails() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, 1, 10);
+        assert_eq!(cache.smart_get(&1, || Err::<usize, _>("down")), Err("down"));
+
+        cache.set_serve_stale_on_error(true);
+        assert_eq!(cache.smart_get(&1, || Err::<usize, _>("down")), Ok(&10));
+        assert_eq!(cache.is_expired(&1), Some(true));
+    }
+
+    #[test]
+    fn should_smart_get_error_when_generation_fails_without_stale_value() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_serve_stale_on_error(true);
+        assert_eq!(cache.smart_get(&1, || Err::<usize, _>("down")), Err("down"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_always_regenerate_uncacheable_keys() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_uncacheable(|key| *key == 0);
+        let generated = AtomicUsize::new(0);
+        let generate_value = || generated.fetch_add(1, Ordering::SeqCst) + 1;
+
+        assert_eq!(cache.get(&0, generate_value), &1);
+        assert_eq!(cache.get(&0, generate_value), &2);
+        assert_eq!(cache.get_owned_key(0, generate_value), &3);
+        assert_eq!(cache.get(&1, generate_value), &4);
+        assert_eq!(cache.get(&1, generate_value), &4);
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get_if_present(&0), None);
+    }
+
+    #[test]
+    fn should_get_two_distinct_keys_at_once() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+
+        let both = cache.get_disjoint(&1, &2, |key| key * 20);
+        assert_eq!(both, Some((&10, &40)));
+        assert_eq!(cache.key_stats(&1), Some((1, 0)));
+        assert_eq!(cache.key_stats(&2), Some((0, 1)));
+    }
+
+    #[test]
+    fn should_not_get_two_keys_at_once_if_storing_second_evicts_first() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_capacity(2);
+        cache.set_regeneration_cooldown(Duration::from_secs(60));
+        insert_expired(&mut cache, 1, 10);
+        cache.insert(3, 30);
+
+        assert_eq!(cache.get_disjoint(&1, &2, |key| key * 20), None);
+        assert_eq!(cache.get_if_present(&2), Some(&40));
+
+        cache.set_uncacheable(|key| *key == 4);
+        assert_eq!(cache.get_disjoint(&2, &4, |key| key * 20), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_getting_same_key_twice_at_once() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let _ = cache.get_disjoint(&1, &1, |key| *key);
+    }
+
+    #[test]
+    fn should_initialize_value_once_ignoring_time_to_keep() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let inits = AtomicUsize::new(0);
+
+        for _ in 0..5 {
+            let value = cache.get_or_init(&1, || inits.fetch_add(1, Ordering::SeqCst) + 10);
+            assert_eq!(value, &10);
+            clock.advance(Duration::from_secs(60));
+        }
+        cache.purge_expired();
+
+        assert_eq!(cache.get_or_init(&1, || unreachable!()), &10);
+        assert_eq!(inits.load(Ordering::SeqCst), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_apply_time_to_live_from_generated_value() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let ttl_fn = |value: &usize| Duration::from_secs(*value as u64);
+
+        let value = cache.try_get_with_ttl_fn(&1, || Ok::<_, ()>(60), ttl_fn);
+        assert_eq!(value, Ok(&60));
+        assert_eq!(cache.store[&1].time_to_live, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn should_not_store_when_generation_with_time_to_live_fails() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+
+        let value = cache.try_get_with_ttl_fn(&1, || Err("down"), |_| unreachable!());
+        assert_eq!(value, Err("down"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_map_keys_to_whether_they_are_valid() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 20]);
+
+        let validity = cache.validity_map(&[0, 1, 2]);
+
+        let expected: HashMap<_, _> = vec![(0, true), (1, false), (2, false)]
+            .into_iter()
+            .collect();
+        assert_eq!(validity, expected);
+    }
+
+    #[test]
+    fn should_check_whether_all_or_any_keys_are_valid() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 0, 20]);
+
+        assert!(cache.contains_all(&[0, 1]));
+        assert!(cache.contains_any(&[0, 1]));
+
+        assert!(!cache.contains_all(&[0, 2]));
+        assert!(cache.contains_any(&[2, 1]));
+
+        assert!(!cache.contains_all(&[2, 3]));
+        assert!(!cache.contains_any(&[2, 3]));
+
+        assert!(cache.contains_all(&[]));
+        assert!(!cache.contains_any(&[]));
+    }
+
+    #[test]
+    fn should_store_default_on_miss() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.get_or(&1, 10), &10);
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+        assert_eq!(cache.key_stats(&1), Some((0, 1)));
+    }
+
+    #[test]
+    fn should_return_clone_of_value_left_stored() {
+        let mut cache = TimedCache::<usize, String>::with_time_to_keep(Duration::from_secs(10));
+
+        assert_eq!(cache.get_or_insert_cloned(&1, "first".to_owned()), "first");
+        assert_eq!(cache.get_or_insert_cloned(&1, "second".to_owned()), "first");
+        assert_eq!(cache.get_if_present(&1), Some(&"first".to_owned()));
+        assert_eq!(cache.key_stats(&1), Some((1, 1)));
+    }
+
+    #[test]
+    fn should_drop_default_on_hit() {
+        let mut cache = TimedCache::<usize, Arc<usize>>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, Arc::new(10));
+
+        let default = Arc::new(20);
+        assert_eq!(cache.get_or(&1, default.clone()), &Arc::new(10));
+        assert_eq!(Arc::strong_count(&default), 1);
+        assert_eq!(cache.key_stats(&1), Some((1, 0)));
+    }
+
+    #[test]
+    fn should_not_clone_owned_key_on_hit_or_repeated_miss() {
+        let mut cache =
+            TimedCache::<CloneCountingKey, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clones = Arc::new(AtomicUsize::new(0));
+        let key = || CloneCountingKey(0, clones.clone());
+
+        cache.get_owned_key(key(), || 1);
+        // Recording statistics for a key seen for the first time needs a clone of it.
+        assert_eq!(clones.load(Ordering::SeqCst), 1);
+
+        cache.get_owned_key(key(), || unreachable!());
+        cache.store.values_mut().for_each(|timed_data| {
+            timed_data.time_stored -= Duration::from_secs(10);
+        });
+        assert_eq!(cache.get_owned_key(key(), || 2), &2);
+
+        assert_eq!(clones.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.key_stats(&key()), Some((1, 2)));
+    }
+
+    #[test]
+    fn should_count_all_entries_by_age_bucket() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 1, 4, 5, 8, 20, 21]);
+
+        assert_eq!(
+            cache.age_histogram(Duration::from_secs(3)),
+            vec![
+                (Duration::from_secs(0), 2),
+                (Duration::from_secs(3), 2),
+                (Duration::from_secs(6), 1),
+                (Duration::from_secs(18), 1),
+                (Duration::from_secs(21), 1)
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket must not be zero")]
+    fn should_reject_zero_age_bucket() {
+        let cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.age_histogram(Duration::from_secs(0));
+    }
+
+    #[test]
+    fn should_count_remaining_ttls_by_bucket() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 1, 4, 5, 8, 9, 20]);
+
+        assert_eq!(
+            cache.remaining_ttl_histogram(Duration::from_secs(3)),
+            vec![
+                (Duration::from_secs(0), 2),
+                (Duration::from_secs(3), 2),
+                (Duration::from_secs(6), 1),
+                (Duration::from_secs(9), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_preserve_remaining_ttls_when_rebasing_clock() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(1, 1);
+        clock.advance(Duration::from_secs(4));
+        cache.insert(2, 2);
+        let histogram = cache.remaining_ttl_histogram(Duration::from_secs(1));
+
+        let new_clock = ManualClock::new();
+        new_clock.advance(Duration::from_secs(3600));
+        cache.rebase_clock(new_clock.clone());
+
+        assert_eq!(
+            cache.remaining_ttl_histogram(Duration::from_secs(1)),
+            histogram
+        );
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(4)));
+        new_clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.get_if_present(&1), None);
+        assert_eq!(cache.get_if_present(&2), Some(&2));
+    }
+
+    #[test]
+    fn should_rebase_far_future_timestamp_without_overflowing() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let latest = ManualClock::new();
+        latest.advance(Duration::MAX);
+        cache.insert_with_timestamp(1, 1, latest.now());
+
+        let new_clock = ManualClock::new();
+        new_clock.advance(Duration::from_secs(3600));
+        cache.rebase_clock(new_clock);
+
+        assert_eq!(cache.get_if_present(&1), Some(&1));
+        assert_eq!(cache.freeze().get_if_present(&1), Some(&1));
+    }
+
+    #[test]
+    fn should_judge_validity_at_given_instant() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let start = Instant::now();
+        let key = KEY.to_owned();
+
+        assert_eq!(cache.get_at(&key, start, || 1), &1);
+        let almost_expired = start + Duration::from_secs(9);
+        assert_eq!(cache.get_at(&key, almost_expired, || 2), &1);
+        let expired = start + Duration::from_secs(10);
+        assert_eq!(cache.get_at(&key, expired, || 2), &2);
+        assert_eq!(
+            cache.get_at(&key, expired + Duration::from_secs(9), || 3),
+            &2
+        );
+        assert_eq!(cache.key_stats(&key), Some((2, 2)));
+    }
+
+    #[test]
+    fn should_regenerate_value_after_manual_clock_advances_past_time_to_keep() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(KEY.to_owned(), 1);
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(cache.get(&KEY.to_owned(), || 2), &1);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+        assert_eq!(cache.get(&KEY.to_owned(), || 2), &2);
+        assert_eq!(cache.age(&KEY.to_owned()), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn should_get_only_valid_entries_with_matching_keys() {
+        let mut cache = TimedCache::<&str, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert("tenant-a/1", 1);
+        cache.insert("tenant-a/2", 2);
+        cache.insert("tenant-b/1", 3);
+        insert_expired(&mut cache, "tenant-a/3", 4);
+
+        let mut matching = cache.get_matching(|key| key.starts_with("tenant-a/"));
+        matching.sort();
+
+        assert_eq!(matching, vec![(&"tenant-a/1", &1), (&"tenant-a/2", &2)]);
+    }
+
+    #[test]
+    fn should_get_existing_value_keeping_its_ttl() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(KEY.to_owned(), 1);
+
+        let value = cache.get_or_insert_with_ttl(
+            KEY.to_owned(),
+            Duration::from_secs(60),
+            || unreachable!(),
+        );
+        assert_eq!(value, &1);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+    }
+
+    #[test]
+    fn should_get_existing_value_without_calling_full_generator() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.insert(KEY.to_owned(), 1);
+
+        let value = cache.get_or_insert_full(KEY.to_owned(), |_| unreachable!());
+        assert_eq!(value, &1);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+    }
+
+    #[test]
+    fn should_insert_missing_value_with_ttl_from_full_generator() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+
+        let value =
+            cache.get_or_insert_full(KEY.to_owned(), |key| (key.len(), Duration::from_secs(60)));
+        assert_eq!(value, &KEY.len());
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&KEY.len()));
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+    }
+
+    #[test]
+    fn should_insert_missing_value_with_custom_ttl() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+
+        let value = cache.get_or_insert_with_ttl(KEY.to_owned(), Duration::from_secs(60), || 2);
+        assert_eq!(value, &2);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&2));
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+    }
+
+    #[test]
+    fn should_notify_observers_of_hits_and_misses() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let hits = observed.clone();
+        cache.observe_hits(move |key| hits.lock().unwrap().push(("hit", key.clone())));
+        let misses = observed.clone();
+        cache.observe_misses(move |key| misses.lock().unwrap().push(("miss", key.clone())));
+
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get(&KEY.to_owned(), || 0);
+        cache.get_if_present(&KEY.to_owned());
+
+        assert_eq!(
+            *observed.lock().unwrap(),
+            vec![("miss", KEY.to_owned()), ("hit", KEY.to_owned())]
+        );
+    }
+
+    #[test]
+    fn should_expire_value_inserted_with_old_timestamp() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let stored = clock.now();
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(cache.insert_with_timestamp(KEY.to_owned(), 1, stored), None);
+
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(true));
+        assert_eq!(cache.age(&KEY.to_owned()), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn should_only_reset_timestamp_when_polled_value_changes() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+
+        assert!(cache.insert_if_changed(1, 10));
+        clock.advance(Duration::from_secs(3));
+        assert!(!cache.insert_if_changed(1, 10));
+        clock.advance(Duration::from_secs(3));
+        assert!(!cache.insert_if_changed(1, 10));
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(6)));
+
+        assert!(cache.insert_if_changed(1, 11));
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(0)));
+        assert_eq!(cache.get_if_present(&1), Some(&11));
+
+        clock.advance(Duration::from_secs(10));
+        assert!(cache.insert_if_changed(1, 11));
+        assert_eq!(cache.get_if_present(&1), Some(&11));
+    }
+
+    #[test]
+    fn should_compare_caches_by_valid_contents() {
+        let mut first = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        first.insert(1, 10);
+        first.insert(2, 20);
+        insert_expired(&mut first, 3, 30);
+
+        let mut second = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(60));
+        second.get(&2, || 20);
+        second.get(&1, || 10);
+        assert!(first == second);
+
+        second.insert(2, 21);
+        assert!(first != second);
+        second.insert(2, 20);
+        second.insert(3, 30);
+        assert!(first != second);
+    }
+
+    #[test]
+    fn should_tolerate_clock_going_backwards() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let early = ManualClock::new();
+        let late = ManualClock::new();
+        late.advance(Duration::from_secs(60));
+        cache.set_clock(late);
+        cache.insert(1, 10);
+
+        cache.set_clock(early);
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(0)));
+        assert_eq!(cache.is_expired(&1), Some(false));
+        assert_eq!(cache.get(&1, || unreachable!()), &10);
+        assert_eq!(cache.purge_expired(), 0);
+        assert_eq!(
+            cache.remaining_ttl_histogram(Duration::from_secs(5)),
+            vec![(Duration::from_secs(10), 1)]
+        );
+    }
+
+    #[test]
+    fn should_increase_generation_id_on_regeneration() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.generation_id(&1), None);
+        cache.get(&1, || 10);
+        let first = cache.generation_id(&1).unwrap();
+
+        cache.get(&1, || unreachable!());
+        assert_eq!(cache.generation_id(&1), Some(first));
+        assert_eq!(cache.get_if_generation_changed(&1, first), None);
+
+        cache.store.get_mut(&1).unwrap().time_stored -= Duration::from_secs(10);
+        cache.get(&1, || 11);
+        let second = cache.generation_id(&1).unwrap();
+        assert!(second > first);
+        assert_eq!(
+            cache.get_if_generation_changed(&1, first),
+            Some((&11, second))
+        );
+
+        cache.refresh_all(|_| 12, true);
+        assert!(cache.generation_id(&1).unwrap() > second);
+    }
+
+    #[test]
+    fn should_round_trip_valid_entries_through_vec() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 4, 20]);
+
+        let mut entries = cache.to_vec();
+        entries.sort_by_key(|(key, _, _)| *key);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[1].0, entries[1].1), (1, 1));
+        assert!(entries[1].2 <= Duration::from_secs(6));
+        assert!(entries[1].2 > Duration::from_secs(5));
+
+        let restored = TimedCache::from_vec(Duration::from_secs(10), entries);
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get_if_present(&0), Some(&0));
+        assert_eq!(restored.get_if_present(&1), Some(&1));
+        let remaining = restored.store[&1]
+            .remaining_at(Duration::from_secs(10), Instant::now())
+            .unwrap();
+        assert!(remaining <= Duration::from_secs(6));
+        assert!(remaining > Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_log_most_recent_misses_in_order() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        assert_eq!(cache.recent_misses().count(), 0);
+        cache.enable_miss_log(3);
+        let start = clock.now();
+
+        for key in 0..4 {
+            cache.get(&key, || key);
+            clock.advance(Duration::from_secs(1));
+        }
+        cache.get(&3, || unreachable!());
+
+        assert_eq!(
+            cache.recent_misses().collect::<Vec<_>>(),
+            vec![
+                (start + Duration::from_secs(1), &1),
+                (start + Duration::from_secs(2), &2),
+                (start + Duration::from_secs(3), &3)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_give_keys_time_to_live_of_first_matching_rule() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.add_ttl_rule(|key| key.starts_with("short:"), Duration::from_secs(2));
+        cache.add_ttl_rule(|key| key.starts_with("s"), Duration::from_secs(5));
+        for key in &["short:a", "session", "other"] {
+            cache.insert(key.to_string(), 1);
+        }
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.get_if_present(&"short:a".to_owned()), None);
+        assert_eq!(cache.get_if_present(&"session".to_owned()), Some(&1));
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(cache.get_if_present(&"session".to_owned()), None);
+        assert_eq!(cache.get_if_present(&"other".to_owned()), Some(&1));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get_if_present(&"other".to_owned()), None);
+    }
+
+    #[test]
+    fn should_grow_time_to_live_of_stable_values() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_adaptive_ttl(Duration::from_secs(30));
+        let ttl = |cache: &TimedCache<usize, usize>| {
+            cache.store[&1]
+                .time_to_live
+                .unwrap_or(Duration::from_secs(10))
+        };
+
+        cache.get(&1, || 10);
+        assert_eq!(ttl(&cache), Duration::from_secs(10));
+        for expected in &[20, 30, 30] {
+            clock.advance(ttl(&cache));
+            cache.get(&1, || 10);
+            assert_eq!(ttl(&cache), Duration::from_secs(*expected));
+        }
+
+        clock.advance(ttl(&cache));
+        cache.get(&1, || 11);
+        assert_eq!(ttl(&cache), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn should_keep_rule_time_to_live_of_stable_values() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_adaptive_ttl(Duration::from_secs(30));
+        cache.add_ttl_rule(|key| *key == 1, Duration::from_secs(5));
+
+        for _ in 0..3 {
+            cache.get(&1, || 10);
+            assert_eq!(cache.store[&1].time_to_live, Some(Duration::from_secs(5)));
+            clock.advance(Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn should_raise_adapted_time_to_live_to_minimum() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_min_ttl(Duration::from_secs(40));
+        cache.set_adaptive_ttl(Duration::from_secs(30));
+
+        cache.get(&1, || 10);
+        clock.advance(Duration::from_secs(40));
+        cache.get(&1, || 10);
+
+        assert_eq!(cache.store[&1].time_to_live, Some(Duration::from_secs(40)));
+    }
+
+    #[test]
+    fn should_track_rate_of_unchanged_regenerations() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.enable_regeneration_tracking();
+        assert_eq!(cache.unchanged_regeneration_rate(), 0.0);
+
+        for value in &[1, 1, 1, 2, 2] {
+            cache.get(&1, || *value);
+            clock.advance(Duration::from_secs(10));
+        }
+        cache.insert(1, 3);
+
+        assert_eq!(cache.unchanged_regeneration_rate(), 0.75);
+    }
+
+    #[test]
+    fn should_count_entries_invalidated_by_predicate() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let evictions = record_evictions(&mut cache);
+        insert_aged(&mut cache, &[0, 0, 20, 0]);
+
+        assert_eq!(cache.invalidate_matching(|key| key % 2 == 0), 2);
+        assert_eq!(cache.keys_sorted().collect::<Vec<_>>(), vec![&1, &3]);
+        assert_eq!(evictions.lock().unwrap().len(), 2);
+        assert_eq!(cache.invalidate_matching(|_| false), 0);
+    }
+
+    #[test]
+    fn should_clear_half_open_key_range() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0; 6]);
+
+        assert_eq!(cache.clear_range(1..4), 3);
+        assert_eq!(cache.keys_sorted().collect::<Vec<_>>(), vec![&0, &4, &5]);
+        assert_eq!(cache.clear_range(5..), 1);
+        assert_eq!(cache.clear_range(..), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_estimate_generation_latency_percentiles() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.generation_latency_percentile(0.5), None);
+        for millis in (1..=100).rev() {
+            cache.record_generation_latency(Duration::from_millis(millis));
+        }
+
+        let median = cache.generation_latency_percentile(0.5).unwrap();
+        assert!(median >= Duration::from_millis(49) && median <= Duration::from_millis(52));
+        assert_eq!(
+            cache.generation_latency_percentile(0.99),
+            Some(Duration::from_millis(99))
+        );
+        assert_eq!(
+            cache.generation_latency_percentile(1.0),
+            Some(Duration::from_millis(100))
+        );
+
+        cache.get(&1, || 1);
+        assert_eq!(cache.generation_latencies.len(), 101);
+    }
+
+    #[test]
+    fn should_refresh_oldest_entries_up_to_limit() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[3, 8, 1, 12, 5]);
+        let stored = cache.store[&0].time_stored;
+
+        assert_eq!(cache.refresh_gradually(2, |key| key * 100), 2);
+        assert_eq!(cache.get_any(&3), Some((&300, true)));
+        assert_eq!(cache.get_any(&1), Some((&100, true)));
+        assert_eq!(cache.get_any(&4), Some((&4, true)));
+        assert_eq!(cache.store[&0].time_stored, stored);
+
+        assert_eq!(cache.refresh_gradually(2, |key| key * 100), 2);
+        assert_eq!(cache.get_any(&4), Some((&400, true)));
+        assert!(cache.store[&0].time_stored > stored);
+        assert_eq!(cache.get_any(&2), Some((&2, true)));
+
+        assert_eq!(cache.refresh_gradually(10, |key| key * 100), 5);
+        assert_eq!(cache.generation_count(), 9);
+    }
+
+    #[test]
+    fn should_refresh_entries_expiring_soonest_with_their_own_ttls() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[5, 2, 0]);
+        cache.set_entry_ttl(&0, Duration::from_secs(60));
+        cache.set_entry_ttl(&2, Duration::from_secs(1));
+
+        assert_eq!(cache.refresh_gradually(2, |key| key * 100), 2);
+        assert_eq!(cache.get_any(&2), Some((&200, true)));
+        assert_eq!(cache.get_any(&1), Some((&100, true)));
+        assert_eq!(cache.get_any(&0), Some((&0, true)));
+    }
+
+    #[test]
+    fn should_list_owned_valid_keys_for_removal() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 20, 0]);
+
+        let mut keys = cache.keys_vec();
+        keys.sort();
+        assert_eq!(keys, vec![0, 2]);
+
+        for key in keys {
+            cache.remove(&key);
+        }
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.is_expired(&1), Some(true));
+    }
+
+    #[test]
+    fn should_expire_roughly_the_given_fraction_of_entries() {
+        let filled = || {
+            let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+            insert_aged(&mut cache, &[0; 1000]);
+            cache
+        };
+
+        let mut cache = filled();
+        let removed = cache.expire_random_fraction(0.25, 7);
+        assert!(removed > 200 && removed < 300, "removed {}", removed);
+        assert_eq!(cache.len(), 1000 - removed);
+        assert_eq!(filled().expire_random_fraction(0.25, 7), removed);
+
+        assert_eq!(filled().expire_random_fraction(0.0, 7), 0);
+        assert_eq!(filled().expire_random_fraction(1.0, 7), 1000);
+    }
+
+    #[test]
+    fn should_restore_entry_aged_by_wall_clock_time() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let stored_at = SystemTime::now() - Duration::from_secs(4);
+
+        assert_eq!(cache.insert_with_system_time(1, 10, stored_at), None);
+        let age = cache.age(&1).unwrap();
+        assert!(age >= Duration::from_secs(4) && age < Duration::from_secs(5));
+
+        let stored_at = SystemTime::now() - Duration::from_secs(60);
+        cache.insert_with_system_time(2, 20, stored_at);
+        assert_eq!(cache.is_expired(&2), Some(true));
+    }
+
+    #[test]
+    fn should_restore_entry_as_fresh_when_wall_clock_moved_backwards() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let stored_at = SystemTime::now() + Duration::from_secs(60);
+
+        cache.insert_with_system_time(1, 10, stored_at);
+        assert!(cache.age(&1).unwrap() < Duration::from_secs(1));
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+    }
+
+    #[test]
+    fn should_rank_hot_keys_by_access_count() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        for (key, accesses) in &[(0, 3), (1, 5), (2, 1), (3, 4)] {
+            for _ in 0..*accesses {
+                cache.get(key, || *key);
+            }
+        }
+        for _ in 0..10 {
+            cache.get(&4, || 4);
+        }
+        cache.store.get_mut(&4).unwrap().time_stored -= Duration::from_secs(10);
+
+        assert_eq!(cache.hot_keys(3), vec![(&1, 5), (&3, 4), (&0, 3)]);
+        assert_eq!(cache.hot_keys(10).len(), 4);
+        assert!(cache.hot_keys(0).is_empty());
+    }
+
+    #[test]
+    fn should_find_least_accessed_valid_keys() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        for (key, accesses) in &[(0, 3), (1, 5), (2, 1)] {
+            for _ in 0..*accesses {
+                cache.get(key, || *key);
+            }
+        }
+        cache.insert(3, 3);
+        insert_expired(&mut cache, 4, 4);
+
+        assert_eq!(cache.cold_keys(2), vec![&3, &2]);
+        assert_eq!(cache.cold_keys(10), vec![&3, &2, &0, &1]);
+    }
+
+    #[test]
+    fn should_back_off_generation_after_repeated_failures() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.set_generation_backoff(Duration::from_secs(1), Duration::from_secs(3));
+        let calls = AtomicUsize::new(0);
+        let failing = || -> Result<usize, ()> {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(())
+        };
+        let key = KEY.to_owned();
+
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::Failed(()))
+        );
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::BackingOff(Duration::from_secs(1)))
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::Failed(()))
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::BackingOff(Duration::from_secs(1)))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.try_get_with_backoff(&key, || Ok::<_, ()>(7)), Ok(&7));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::Failed(()))
+        );
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(cache.try_get_with_backoff(&key, failing), Ok(&7));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(
+            cache.try_get_with_backoff(&key, failing),
+            Err(BackoffError::Failed(()))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn should_restore_entries_with_their_own_timestamps() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let old = clock.now();
+        clock.advance(Duration::from_secs(15));
+        let recent = clock.now() - Duration::from_secs(5);
+
+        cache.insert_all_with_timestamps(vec![(1, 10, old), (2, 20, recent), (3, 30, clock.now())]);
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.is_expired(&1), Some(true));
+        assert_eq!(cache.is_expired(&2), Some(false));
+        assert_eq!(cache.age(&2), Some(Duration::from_secs(5)));
+        assert_eq!(cache.get_if_present(&3), Some(&30));
+    }
+
+    #[test]
+    fn should_report_whether_regenerated_value_changed() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+
+        assert_eq!(
+            cache.get_reporting_changed(&KEY.to_owned(), || 1),
+            (&1, true)
+        );
+        assert_eq!(
+            cache.get_reporting_changed(&KEY.to_owned(), || 2),
+            (&1, false)
+        );
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            cache.get_reporting_changed(&KEY.to_owned(), || 1),
+            (&1, false)
+        );
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            cache.get_reporting_changed(&KEY.to_owned(), || 2),
+            (&2, true)
+        );
+    }
+
+    #[test]
+    fn should_expire_only_entries_older_than_age() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(60));
+        insert_aged(&mut cache, &[1, 5, 20, 40]);
+
+        assert_eq!(cache.expire_older_than(Duration::from_secs(10)), 2);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&0), Some(&0));
+        assert_eq!(cache.peek(&1), Some(&1));
+    }
+
+    #[test]
+    fn should_record_time_taken_by_last_generation() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.last_generation_time(&KEY.to_owned()), None);
+
+        cache.get(&KEY.to_owned(), || {
+            sleep(Duration::from_millis(20));
+            0
+        });
+
+        let generation_time = cache.last_generation_time(&KEY.to_owned()).unwrap();
+        assert!(generation_time >= Duration::from_millis(20));
+        assert!(generation_time < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_consume_only_valid_entries() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 20, 5, 30]);
+
+        let mut valid: Vec<_> = cache.into_valid().collect();
+        valid.sort();
+
+        assert_eq!(valid, vec![(0, 0), (2, 2)]);
+    }
+
+    #[test]
+    fn should_notify_each_purged_entry_as_expired() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let evictions = record_evictions(&mut cache);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        clock.advance(Duration::from_secs(5));
+        cache.insert(3, 30);
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(cache.purge_expired(), 2);
+        let mut evictions = evictions.lock().unwrap().clone();
+        evictions.sort_by_key(|&(key, _, _)| key);
+        assert_eq!(
+            evictions,
+            vec![
+                (1, 10, EvictionReason::Expired),
+                (2, 20, EvictionReason::Expired)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_send_evicted_entries_down_channel() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let (sender, receiver) = channel();
+        cache.set_eviction_channel(sender);
+        cache.insert(1, 10);
+        cache.insert(1, 11);
+        insert_expired(&mut cache, 2, 20);
+        cache.purge_expired();
+        cache.remove(&1);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![(1, 10), (2, 20), (1, 11)]
+        );
+    }
+
+    #[test]
+    fn should_keep_evicting_after_channel_receiver_dropped() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let (sender, receiver) = channel();
+        cache.set_eviction_channel(sender);
+        drop(receiver);
+        cache.insert(1, 10);
+
+        assert_eq!(cache.remove(&1), Some(10));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_regenerate_value_older_than_max_age_within_time_to_keep() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[1, 5]);
+
+        assert_eq!(
+            cache.get_fresh_within(&0, Duration::from_secs(2), || 10),
+            &0
+        );
+        assert_eq!(
+            cache.get_fresh_within(&1, Duration::from_secs(2), || 11),
+            &11
+        );
+        assert_eq!(
+            cache.get_fresh_within(&1, Duration::from_secs(20), || 12),
+            &11
+        );
+    }
+
+    #[test]
+    fn should_keep_most_recent_size_samples() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let start = clock.now();
+        cache.record_size_sample();
+        cache.enable_size_sampling(2);
+
+        for key in 0..3 {
+            cache.insert(key, key);
+            cache.record_size_sample();
+            clock.advance(Duration::from_secs(1));
+        }
+
+        assert_eq!(
+            cache.size_samples().collect::<Vec<_>>(),
+            vec![
+                (start + Duration::from_secs(1), 2),
+                (start + Duration::from_secs(2), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_clamp_time_to_live_below_minimum() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_micros(5));
+        cache.insert(0, 0);
+        cache.set_entry_ttl(&0, Duration::from_micros(1));
+        cache.set_min_ttl(Duration::from_secs(1));
+        cache.insert(1, 1);
+        cache.set_entry_ttl(&1, Duration::from_millis(1));
+
+        assert_eq!(cache.time_to_keep, Duration::from_secs(1));
+        assert_eq!(cache.store[&0].time_to_live, Some(Duration::from_secs(1)));
+        assert_eq!(cache.store[&1].time_to_live, Some(Duration::from_secs(1)));
+        assert_eq!(cache.clamped_ttls(), 3);
+
+        cache.set_entry_ttl(&1, Duration::from_secs(5));
+        assert_eq!(cache.clamped_ttls(), 3);
+    }
+
+    #[test]
+    fn should_change_fingerprint_only_when_regenerated_value_changes() {
+        let mut cache = TimedCache::<String, String>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.fingerprint(&KEY.to_owned()), None);
+        cache.insert(KEY.to_owned(), "one".to_owned());
+        let original = cache.fingerprint(&KEY.to_owned());
+
+        insert_expired(&mut cache, KEY.to_owned(), "one".to_owned());
+        cache.get(&KEY.to_owned(), || "one".to_owned());
+        assert_eq!(cache.fingerprint(&KEY.to_owned()), original);
+
+        insert_expired(&mut cache, KEY.to_owned(), "one".to_owned());
+        cache.get(&KEY.to_owned(), || "two".to_owned());
+        assert_ne!(cache.fingerprint(&KEY.to_owned()), original);
+    }
+
+    #[test]
+    fn should_purge_automatically_once_enough_entries_expire() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_auto_purge(0.5);
+        for key in 0..100 {
+            insert_expired(&mut cache, key, key);
+        }
+
+        for key in 100..163 {
+            cache.insert(key, key);
+        }
+        assert_eq!(cache.len(), 163);
+
+        cache.insert(163, 163);
+        assert_eq!(cache.len(), 64);
+    }
+
+    #[test]
+    fn should_expire_value_after_advancing_time_for_test() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(3600));
+        cache.insert(KEY.to_owned(), 1);
+
+        cache.advance_time_for_test(Duration::from_secs(1800));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), Some(&1));
+
+        cache.advance_time_for_test(Duration::from_secs(1800));
+        assert_eq!(cache.get_if_present(&KEY.to_owned()), None);
+        assert_eq!(cache.get(&KEY.to_owned(), || 2), &2);
+    }
+
+    #[test]
+    fn should_snapshot_stats_after_several_operations() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.get(&0, || 0);
+        cache.get(&0, || unreachable!());
+        cache.get(&1, || 1);
+        cache.insert(1, 10);
+        cache.insert(2, 2);
+        cache.set_entry_ttl(&2, Duration::from_secs(0));
+        let _ = cache.remove(&0);
+
+        let snapshot = cache.stats_snapshot();
+
+        assert_eq!(
+            snapshot,
+            CacheStatsSnapshot {
+                hits: 1,
+                misses: 2,
+                generations: 2,
+                evictions: 2,
+                len: 2,
+                valid_len: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn should_retain_only_newest_entries() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let evictions = record_evictions(&mut cache);
+        for key in 0..3 {
+            cache.insert(key, key);
+            clock.advance(Duration::from_secs(1));
+        }
+        cache.insert(3, 3);
+        cache.insert(4, 4);
+
+        assert_eq!(cache.retain_newest(5), 0);
+        assert_eq!(cache.retain_newest(2), 3);
+        let mut kept: Vec<_> = cache.iter().map(|(key, _)| *key).collect();
+        kept.sort();
+        assert_eq!(kept, vec![3, 4]);
+        assert_eq!(evictions.lock().unwrap().len(), 3);
+        assert!(evictions
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|&(_, _, reason)| reason == EvictionReason::Capacity));
+
+        assert_eq!(cache.retain_newest(0), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_count_generations_by_get_but_not_insert() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(0, 0);
+        cache.write_through(1, 1);
+        cache.get(&0, || unreachable!());
+        assert_eq!(cache.generation_count(), 0);
+
+        cache.get(&2, || 2);
+        cache.get_or_insert_with_ttl(3, Duration::from_secs(1), || 3);
+        assert_eq!(cache.generation_count(), 2);
+    }
+
+    #[test]
+    fn should_count_each_generation_attempt() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let _ = cache.try_get_with_retries(&0, || Err::<usize, _>(()), 2);
+        let _ = cache.try_get_catching(&1, || panic!("generation failed"));
+        assert_eq!(cache.generation_count(), 4);
+    }
+
+    #[test]
+    fn should_count_generations_by_refresh_and_prewarm() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.prewarm(&[0, 1, 2], |key| *key), 3);
+        cache.refresh_all(|key| *key, true);
+        assert_eq!(cache.generation_count(), 6);
+    }
+
+    #[test]
+    fn should_cache_successful_result_for_time_to_keep() {
+        let mut cache =
+            TimedCache::<String, Result<usize, String>>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let error_ttl = Duration::from_secs(1);
+
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || Ok(1)),
+            Ok(&1)
+        );
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || unreachable!()),
+            Ok(&1)
+        );
+    }
+
+    #[test]
+    fn should_return_cached_error_within_error_ttl() {
+        let mut cache =
+            TimedCache::<String, Result<usize, String>>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let error_ttl = Duration::from_secs(1);
+
+        let failure = || Err("unavailable".to_owned());
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, failure),
+            Err("unavailable".to_owned())
+        );
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || unreachable!()),
+            Err("unavailable".to_owned())
+        );
+    }
+
+    #[test]
+    fn should_return_uncacheable_results_without_caching_errors() {
+        let mut cache =
+            TimedCache::<String, Result<usize, String>>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_uncacheable(|_| true);
+        let error_ttl = Duration::from_secs(1);
+
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || Ok(1)),
+            Ok(&1)
+        );
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || Err("down".to_owned())),
+            Err("down".to_owned())
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_retry_after_cached_error_expires() {
+        let mut cache =
+            TimedCache::<String, Result<usize, String>>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        let error_ttl = Duration::from_secs(1);
+
+        let failure = || Err("unavailable".to_owned());
+        let _ = cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, failure);
+        clock.advance(error_ttl);
+        assert_eq!(
+            cache.try_get_caching_errors(&KEY.to_owned(), error_ttl, || Ok(2)),
+            Ok(&2)
+        );
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.is_expired(&KEY.to_owned()), Some(false));
+    }
+
+    #[test]
+    fn should_take_valid_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(KEY.to_owned(), 1);
+        assert_eq!(cache.take_valid(&KEY.to_owned()), Some(1));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_leave_expired_value_in_place_when_taking() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_expired(&mut cache, KEY.to_owned(), 1);
+        assert_eq!(cache.take_valid(&KEY.to_owned()), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn should_not_take_absent_value() {
+        let mut cache = TimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(cache.take_valid(&KEY.to_owned()), None);
+    }
+
+    #[test]
+    fn should_partition_valid_and_expired_entries() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        insert_aged(&mut cache, &[0, 20, 5, 30]);
+        let expired_at = cache.store[&1].time_stored;
+
+        let (valid, expired) = cache.partition_expired();
+
+        let mut valid_keys: Vec<_> = valid.store.keys().cloned().collect();
+        valid_keys.sort();
+        let mut expired_keys: Vec<_> = expired.store.keys().cloned().collect();
+        expired_keys.sort();
+        assert_eq!(valid_keys, vec![0, 2]);
+        assert_eq!(expired_keys, vec![1, 3]);
+        assert_eq!(expired.store[&1].time_stored, expired_at);
+        assert_eq!(expired.is_expired(&1), Some(true));
+    }
 }
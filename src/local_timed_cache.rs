@@ -0,0 +1,148 @@
+use std::cell::{Ref, RefCell};
+use std::hash::Hash;
+use std::time::Duration;
+use TimedCache;
+
+///
+/// A `TimedCache` which can generate and store values on a miss while only borrowed immutably,
+/// using a `RefCell` internally.
+///
+/// This allows the cache to be shared within a single thread, such as by holding it in an `Rc`,
+/// while still being able to fill itself. It is not `Sync`, so it cannot be shared between
+/// threads; use `SyncTimedCache` for that.
+///
+/// Borrows of the cache are checked at runtime rather than compile time. Storing a value needs
+/// exclusive access to the cache, so `LocalTimedCache::get` panics if any reference it returned
+/// earlier is still held, or if it is called again from within the generator.
+///
+pub struct LocalTimedCache<Key: Hash + Eq, Value> {
+    cache: RefCell<TimedCache<Key, Value>>,
+}
+
+impl<Key: Hash + Eq, Value> LocalTimedCache<Key, Value> {
+    ///
+    /// Creates a `LocalTimedCache` with the specified `Duration` as the length of time the values
+    /// will be considered 'valid' after initial storage.
+    ///
+    pub fn with_time_to_keep(time_to_keep: Duration) -> LocalTimedCache<Key, Value> {
+        LocalTimedCache {
+            cache: RefCell::new(TimedCache::with_time_to_keep(time_to_keep)),
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` only if it exists
+    /// and is still considered valid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within the generator passed to `LocalTimedCache::get`.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<Ref<'_, Value>> {
+        let cache = self.cache.borrow();
+        let _ = cache.get_if_present(key)?;
+        Some(Ref::map(cache, |cache| {
+            cache.get_if_present(key).expect("value should be valid")
+        }))
+    }
+}
+
+///
+/// The methods which store a value for a key given by reference, so need `Key: Clone`.
+///
+impl<Key: Hash + Eq + Clone, Value> LocalTimedCache<Key, Value> {
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and is
+    /// still considered valid, otherwise generates, stores and returns the value, like
+    /// `TimedCache::get`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a reference returned by a previous call is still held, or if called from
+    /// within `generate_value`.
+    ///
+    pub fn get(&self, key: &Key, generate_value: impl Fn() -> Value) -> Ref<'_, Value> {
+        self.cache.borrow_mut().ensure(key, generate_value);
+        Ref::map(self.cache.borrow(), |cache| &cache.store[key].item)
+    }
+
+    ///
+    /// Retrieves a reference to the value initialized for the `key`, calling `init` to initialize
+    /// it if this is the first time it has been asked for, like `TimedCache::get_or_init` but
+    /// through a shared reference, as with `OnceCell::get_or_init`.
+    ///
+    /// Once the value for the `key` is initialized, retrieving it only borrows the cache
+    /// immutably, so references to initialized values can be held at the same time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value needs initializing while a reference returned by a previous call is
+    /// still held, or if called from within `init` or the generator passed to
+    /// `LocalTimedCache::get`.
+    ///
+    pub fn get_or_init(&self, key: &Key, init: impl FnOnce() -> Value) -> Ref<'_, Value> {
+        if !self.cache.borrow().initialized.contains_key(key) {
+            let _ = self.cache.borrow_mut().get_or_init(key, init);
+        }
+        Ref::map(self.cache.borrow(), |cache| &cache.initialized[key])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalTimedCache;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    const KEY: &str = "test";
+
+    #[test]
+    fn should_get_through_shared_reference_held_in_rc() {
+        let cache = Rc::new(LocalTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let shared = cache.clone();
+        let generations = Cell::new(0);
+        let generate = || {
+            generations.set(generations.get() + 1);
+            generations.get()
+        };
+
+        assert_eq!(*cache.get(&KEY.to_owned(), generate), 1);
+        assert_eq!(*shared.get(&KEY.to_owned(), generate), 1);
+        assert_eq!(generations.get(), 1);
+        assert_eq!(
+            shared.get_if_present(&KEY.to_owned()).map(|value| *value),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn should_initialize_value_once_through_shared_reference() {
+        let cache = Rc::new(LocalTimedCache::<usize, usize>::with_time_to_keep(
+            Duration::from_secs(0),
+        ));
+        let shared = cache.clone();
+        let inits = Cell::new(0);
+        let init = || {
+            inits.set(inits.get() + 1);
+            10
+        };
+
+        let first = cache.get_or_init(&1, init);
+        let held: Vec<_> = (0..5).map(|_| shared.get_or_init(&1, init)).collect();
+
+        assert_eq!(*first, 10);
+        assert!(held.iter().all(|value| **value == 10));
+        assert_eq!(inits.get(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_getting_while_earlier_reference_held() {
+        let cache = LocalTimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let _held = cache.get(&0, || 0);
+        let _ = cache.get(&1, || 1);
+    }
+}
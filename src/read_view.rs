@@ -0,0 +1,94 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use TimedCache;
+
+///
+/// A read-only view of a `TimedCache`, returned by `TimedCache::read_view`.
+///
+/// The view can only look up values which are already stored and still valid, so code given a
+/// view can never cause a value to be generated or the cache to be modified.
+///
+pub struct ReadView<'a, Key: Hash + Eq, Value, S = RandomState> {
+    cache: &'a TimedCache<Key, Value, S>,
+}
+
+impl<'a, Key: Hash + Eq, Value, S> Clone for ReadView<'a, Key, Value, S> {
+    fn clone(&self) -> ReadView<'a, Key, Value, S> {
+        *self
+    }
+}
+
+impl<'a, Key: Hash + Eq, Value, S> Copy for ReadView<'a, Key, Value, S> {}
+
+impl<'a, Key: Hash + Eq, Value, S: BuildHasher> ReadView<'a, Key, Value, S> {
+    pub(crate) fn new(cache: &'a TimedCache<Key, Value, S>) -> ReadView<'a, Key, Value, S> {
+        ReadView { cache }
+    }
+
+    ///
+    /// Retrieves a reference to the value stored in the cache for the `key` if it exists and
+    /// is still considered valid, like `TimedCache::get_if_present`.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<&'a Value> {
+        self.cache.get_if_present(key)
+    }
+
+    ///
+    /// Checks whether a valid value is stored in the cache for the `key`.
+    ///
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.get_if_present(key).is_some()
+    }
+
+    ///
+    /// Returns the number of entries stored in the cache, including any which are no longer
+    /// considered valid, like `TimedCache::len`.
+    ///
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    ///
+    /// Returns `true` if the cache has no entries stored, like `TimedCache::is_empty`.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    ///
+    /// Iterates over every entry in the cache that is still considered valid, in an arbitrary
+    /// order, like `TimedCache::iter`.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&'a Key, &'a Value)> + 'a {
+        self.cache.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadView;
+    use std::time::Duration;
+    use TimedCache;
+
+    fn count_valid(view: ReadView<'_, usize, usize>) -> usize {
+        view.iter().count()
+    }
+
+    #[test]
+    fn should_read_cache_through_view() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+
+        let view = cache.read_view();
+        let other_view = cache.read_view();
+
+        assert_eq!(view.get_if_present(&1), Some(&10));
+        assert!(other_view.contains_key(&2));
+        assert!(!view.contains_key(&3));
+        assert_eq!(view.len(), 2);
+        assert!(!view.is_empty());
+        assert_eq!(count_valid(view), 2);
+        assert_eq!(cache.peek(&1), Some(&10));
+    }
+}
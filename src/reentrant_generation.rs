@@ -0,0 +1,20 @@
+use std::error::Error;
+use std::fmt;
+
+///
+/// The error returned when a generator tries to retrieve the value it is itself generating,
+/// which could otherwise never finish.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReentrantGeneration;
+
+impl fmt::Display for ReentrantGeneration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the generator tried to retrieve the value it is generating"
+        )
+    }
+}
+
+impl Error for ReentrantGeneration {}
@@ -0,0 +1,1134 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+use ReentrantGeneration;
+use TimedCache;
+use TimeoutError;
+
+///
+/// A `TimedCache` which can be shared between threads.
+///
+/// When several threads miss on the same key at once, only the first of them generates the
+/// value, and the others wait for it to finish rather than calling the generator themselves.
+///
+/// Values are stored behind an `Arc`, so they can be handed out of the lock cheaply with
+/// `SyncTimedCache::get_arc`.
+///
+/// The cache can be split into shards with `SyncTimedCache::with_shards`, each behind its own
+/// lock, so that threads using keys in different shards do not contend with one another.
+///
+pub struct SyncTimedCache<Key: Hash + Eq + Clone, Value> {
+    ///
+    /// The shards of the cache, each holding the keys routed to it along with the generations
+    /// of those keys currently in progress.
+    ///
+    shards: Vec<Mutex<State<Key, Value>>>,
+    ///
+    /// Hashes keys to route them to a shard.
+    ///
+    hash_builder: RandomState,
+    ///
+    /// How long a thread will wait for another thread's generation before giving up, if set.
+    ///
+    generation_timeout: Option<Duration>,
+    ///
+    /// The permits limiting how many values can be generated at once across all keys, if
+    /// limited.
+    ///
+    generation_permits: Option<Semaphore>,
+    ///
+    /// The number of generators currently running.
+    ///
+    running_generations: AtomicUsize,
+}
+
+struct State<Key: Hash + Eq + Clone, Value> {
+    cache: TimedCache<Key, Arc<Value>>,
+    in_flight: HashMap<Key, Arc<Generation>>,
+}
+
+///
+/// How `SyncTimedCache::get_reporting` or `TimedCache::get_detailed` retrieved a value.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GetOutcome {
+    ///
+    /// A valid value was already stored.
+    ///
+    Hit,
+    ///
+    /// This thread generated the value, including after giving up waiting for another thread's
+    /// generation.
+    ///
+    Generated,
+    ///
+    /// This thread waited for another thread to generate the value. Only a `SyncTimedCache`
+    /// reports this.
+    ///
+    Waited,
+    ///
+    /// An expired value was served without being regenerated, because its regeneration cooldown
+    /// had not yet passed. Only a `TimedCache` reports this.
+    ///
+    ServedStale,
+}
+
+///
+/// A generation in progress, which other threads can wait to finish.
+///
+struct Generation {
+    finished: Mutex<bool>,
+    finished_signal: Condvar,
+    ///
+    /// The thread running the generation.
+    ///
+    generating_thread: ThreadId,
+    ///
+    /// Whether this is a marker left by `SyncTimedCache::mark_pending`, to be finished by
+    /// `SyncTimedCache::fulfill`, rather than a generator actually running.
+    ///
+    pending: bool,
+}
+
+impl Generation {
+    ///
+    /// Creates a generation being run by the current thread.
+    ///
+    fn new() -> Generation {
+        Generation {
+            finished: Mutex::new(false),
+            finished_signal: Condvar::new(),
+            generating_thread: thread::current().id(),
+            pending: false,
+        }
+    }
+
+    ///
+    /// Creates a marker for a value the current thread will provide later.
+    ///
+    fn pending() -> Generation {
+        Generation {
+            pending: true,
+            ..Generation::new()
+        }
+    }
+
+    fn finish(&self) {
+        *self.finished.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.finished_signal.notify_all();
+    }
+
+    ///
+    /// Waits for the generation to finish, returning `false` if the `timeout` elapsed first.
+    ///
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        let finished = self.finished.lock().unwrap_or_else(PoisonError::into_inner);
+        match timeout {
+            Some(timeout) => {
+                let (_finished, result) = self
+                    .finished_signal
+                    .wait_timeout_while(finished, timeout, |finished| !*finished)
+                    .unwrap_or_else(PoisonError::into_inner);
+                !result.timed_out()
+            }
+            None => {
+                drop(
+                    self.finished_signal
+                        .wait_while(finished, |finished| !*finished)
+                        .unwrap_or_else(PoisonError::into_inner),
+                );
+                true
+            }
+        }
+    }
+}
+
+///
+/// A counting semaphore, used to limit how many generations can run at once.
+///
+struct Semaphore {
+    available: Mutex<usize>,
+    released_signal: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            available: Mutex::new(permits),
+            released_signal: Condvar::new(),
+        }
+    }
+
+    ///
+    /// Waits for a permit to be available and takes it, returning a guard which gives the permit
+    /// back when dropped.
+    ///
+    fn acquire(&self) -> Permit<'_> {
+        let available = self
+            .available
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut available = self
+            .released_signal
+            .wait_while(available, |available| *available == 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self
+            .semaphore
+            .available
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) += 1;
+        self.semaphore.released_signal.notify_one();
+    }
+}
+
+///
+/// Counts a generator as running until dropped, even if the generator panicked.
+///
+struct Running<'a> {
+    running_generations: &'a AtomicUsize,
+}
+
+impl<'a> Running<'a> {
+    fn start(running_generations: &'a AtomicUsize) -> Running<'a> {
+        let _ = running_generations.fetch_add(1, Ordering::SeqCst);
+        Running {
+            running_generations,
+        }
+    }
+}
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        let _ = self.running_generations.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+///
+/// Marks a generation as finished when dropped, even if the generator panicked, so that waiting
+/// threads are never left waiting forever.
+///
+struct InFlight<'a, Key: Hash + Eq + Clone, Value> {
+    cache: &'a SyncTimedCache<Key, Value>,
+    key: &'a Key,
+    generation: Arc<Generation>,
+}
+
+impl<'a, Key: Hash + Eq + Clone, Value> Drop for InFlight<'a, Key, Value> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.cache.lock_state(self.key);
+            // Only remove the generation if it is still this one, in case it was replaced
+            // while the generator ran.
+            let own = state.in_flight.get(self.key).map_or(false, |generation| {
+                Arc::ptr_eq(generation, &self.generation)
+            });
+            if own {
+                let _ = state.in_flight.remove(self.key);
+            }
+        }
+        self.generation.finish();
+    }
+}
+
+impl<Key: Hash + Eq + Clone, Value> SyncTimedCache<Key, Value> {
+    ///
+    /// Creates a `SyncTimedCache` with the specified `Duration` as the length of time the values
+    /// will be considered 'valid' after initial storage.
+    ///
+    pub fn with_time_to_keep(time_to_keep: Duration) -> SyncTimedCache<Key, Value> {
+        SyncTimedCache::with_shards(1, time_to_keep)
+    }
+
+    ///
+    /// Creates a `SyncTimedCache` split into `shards` shards, each with its own lock, with the
+    /// specified `Duration` as the length of time the values will be considered 'valid' after
+    /// initial storage.
+    ///
+    /// Keys are routed to a shard by their hash, so threads working with keys in different
+    /// shards never wait on each other's locks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    ///
+    pub fn with_shards(shards: usize, time_to_keep: Duration) -> SyncTimedCache<Key, Value> {
+        assert!(shards > 0, "a SyncTimedCache needs at least one shard");
+        SyncTimedCache {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(State {
+                        cache: TimedCache::with_time_to_keep(time_to_keep),
+                        in_flight: HashMap::new(),
+                    })
+                })
+                .collect(),
+            hash_builder: RandomState::new(),
+            generation_timeout: None,
+            generation_permits: None,
+            running_generations: AtomicUsize::new(0),
+        }
+    }
+
+    ///
+    /// Sets how long a thread will wait for another thread to generate the value for a key.
+    ///
+    /// If the timeout elapses while the other thread's generation is still in progress, the
+    /// waiting thread stops waiting and generates the value itself. The original generation is
+    /// not cancelled: whichever generation finishes last is the value left in the cache.
+    ///
+    pub fn set_generation_timeout(&mut self, timeout: Duration) {
+        self.generation_timeout = Some(timeout);
+    }
+
+    ///
+    /// Limits how many values can be generated at once across all keys, such as to protect a
+    /// shared backend from too many concurrent fetches.
+    ///
+    /// A thread which needs to generate a value while `limit` generations are already running
+    /// waits for one of them to finish first. This is separate from the coalescing of misses on
+    /// the same key, which still only generates each value once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    ///
+    pub fn set_generation_concurrency_limit(&mut self, limit: usize) {
+        assert!(limit > 0, "limit must be non-zero");
+        self.generation_permits = Some(Semaphore::new(limit));
+    }
+
+    ///
+    /// Retrieves a clone of the value stored in the cache for the `key` if it exists and is
+    /// still considered valid, otherwise generates, stores and returns the value.
+    ///
+    /// If another thread is already generating the value for the `key`, this waits for that
+    /// generation to finish instead of calling `generate_value`. The lock on the cache is not
+    /// held while generating.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within the generator of the value for the same `key`, which would
+    /// otherwise wait forever; use `SyncTimedCache::try_get_arc` to handle that as an error.
+    ///
+    pub fn get(&self, key: &Key, generate_value: impl Fn() -> Value) -> Value
+    where
+        Value: Clone,
+    {
+        (*self.get_arc(key, generate_value)).clone()
+    }
+
+    ///
+    /// Behaves like `SyncTimedCache::get`, but returns a shared reference to the stored value
+    /// rather than cloning it.
+    ///
+    pub fn get_arc(&self, key: &Key, generate_value: impl Fn() -> Value) -> Arc<Value> {
+        self.get_reporting(key, generate_value).0
+    }
+
+    ///
+    /// Behaves like `SyncTimedCache::get_arc`, but returns a `ReentrantGeneration` error if
+    /// called from within the generator of the value for the same `key`, instead of panicking.
+    ///
+    /// Such a call could otherwise never finish, as it would wait for its own generation.
+    ///
+    pub fn try_get_arc(
+        &self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> Result<Arc<Value>, ReentrantGeneration> {
+        self.try_get_reporting(key, generate_value)
+            .map(|(value, _)| value)
+    }
+
+    ///
+    /// Behaves like `SyncTimedCache::get_arc`, but also reports how the value was retrieved, such
+    /// as to measure how often threads contend to generate the same value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within the generator of the value for the same `key`; use
+    /// `SyncTimedCache::try_get_arc` to handle that as an error instead.
+    ///
+    pub fn get_reporting(
+        &self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> (Arc<Value>, GetOutcome) {
+        self.try_get_reporting(key, generate_value)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_get_reporting(
+        &self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+    ) -> Result<(Arc<Value>, GetOutcome), ReentrantGeneration> {
+        let mut waited = false;
+        loop {
+            let generation = {
+                let mut state = self.lock_state(key);
+                if let Some(value) = state.cache.get_if_present(key) {
+                    let outcome = if waited {
+                        GetOutcome::Waited
+                    } else {
+                        GetOutcome::Hit
+                    };
+                    return Ok((value.clone(), outcome));
+                }
+                match state.in_flight.get(key) {
+                    Some(generation) if generation.generating_thread == thread::current().id() => {
+                        return Err(ReentrantGeneration);
+                    }
+                    Some(generation) => generation.clone(),
+                    None => {
+                        let generation = Arc::new(Generation::new());
+                        let _ = state.in_flight.insert(key.clone(), generation.clone());
+                        drop(state);
+                        let value = self.generate(key, generation, generate_value);
+                        return Ok((value, GetOutcome::Generated));
+                    }
+                }
+            };
+            waited = true;
+            if !generation.wait(self.generation_timeout) {
+                if generation.pending {
+                    match self.replace_pending(key, &generation) {
+                        // The value marked as pending was never provided, so this thread takes
+                        // over generating it, and other threads wait for this generation instead.
+                        Some(replacement) => {
+                            let value = self.generate(key, replacement, generate_value);
+                            return Ok((value, GetOutcome::Generated));
+                        }
+                        // The marker was fulfilled or replaced meanwhile, so check again.
+                        None => continue,
+                    }
+                }
+                let value = Arc::new(self.generate_value(generate_value));
+                let _ = self
+                    .lock_state(key)
+                    .cache
+                    .insert(key.clone(), value.clone());
+                return Ok((value, GetOutcome::Generated));
+            }
+            // The generation may have panicked instead of storing a value, so check again.
+        }
+    }
+
+    ///
+    /// Behaves like `SyncTimedCache::get_arc`, but gives up with a `TimeoutError` if the value
+    /// is still being generated by another thread once `timeout` has elapsed, to bound how long
+    /// a caller can be kept waiting.
+    ///
+    /// If no other thread is generating the value, this thread generates it, however long that
+    /// takes; the timeout only limits waiting for other threads. Giving up does not cancel the
+    /// other thread's generation.
+    ///
+    /// If called from within the generator of the value for the same `key`, or by the thread
+    /// which marked it with `SyncTimedCache::mark_pending`, this returns a `TimeoutError` at
+    /// once, as the value could never arrive while this thread waits for it.
+    ///
+    pub fn get_with_timeout(
+        &self,
+        key: &Key,
+        generate_value: impl Fn() -> Value,
+        timeout: Duration,
+    ) -> Result<Arc<Value>, TimeoutError> {
+        // A timeout too long to represent as an instant is as good as no timeout at all.
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let generation = {
+                let mut state = self.lock_state(key);
+                if let Some(value) = state.cache.get_if_present(key) {
+                    return Ok(value.clone());
+                }
+                match state.in_flight.get(key) {
+                    Some(generation) if generation.generating_thread == thread::current().id() => {
+                        return Err(TimeoutError);
+                    }
+                    Some(generation) => generation.clone(),
+                    None => {
+                        let generation = Arc::new(Generation::new());
+                        let _ = state.in_flight.insert(key.clone(), generation.clone());
+                        drop(state);
+                        return Ok(self.generate(key, generation, generate_value));
+                    }
+                }
+            };
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if !generation.wait(remaining) {
+                return Err(TimeoutError);
+            }
+            // The generation may have panicked instead of storing a value, so check again.
+        }
+    }
+
+    ///
+    /// Marks the value for the `key` as about to be provided by `SyncTimedCache::fulfill`, such
+    /// as by a prefetch pipeline, so that other threads missing on the `key` wait for it rather
+    /// than each generating the value themselves.
+    ///
+    /// Waiting threads behave just as if the current thread were generating the value, so they
+    /// are bounded by `SyncTimedCache::set_generation_timeout` if it is set, and otherwise wait
+    /// until the value is provided. The first thread to give up waiting clears the marker and
+    /// generates the value itself, so a value which is never provided does not hold up the
+    /// `key` for good.
+    ///
+    /// Until the value is provided, the current thread is treated as generating it, so retrieving
+    /// the `key` from the current thread is a `ReentrantGeneration` error: `SyncTimedCache::get`
+    /// panics, and `SyncTimedCache::try_get_arc` returns the error.
+    ///
+    /// Returns `false`, without marking anything, if the value for the `key` is already being
+    /// generated or marked as pending.
+    ///
+    pub fn mark_pending(&self, key: Key) -> bool {
+        let mut state = self.lock_state(&key);
+        if state.in_flight.contains_key(&key) {
+            return false;
+        }
+        let _ = state.in_flight.insert(key, Arc::new(Generation::pending()));
+        true
+    }
+
+    ///
+    /// Stores the `value` for the `key`, waking any threads waiting for it after
+    /// `SyncTimedCache::mark_pending`, and returns `true`.
+    ///
+    /// This can be called for any key, marked or not, so it is also a way to insert a value.
+    /// It returns `false`, without storing the `value`, if a generator is running for the `key`
+    /// instead, as that generation would overwrite the value once it finished.
+    ///
+    pub fn fulfill(&self, key: Key, value: Value) -> bool {
+        let generation = {
+            let mut state = self.lock_state(&key);
+            let generation = match state.in_flight.get(&key) {
+                Some(generation) if !generation.pending => return false,
+                Some(_) => state.in_flight.remove(&key),
+                None => None,
+            };
+            let _ = state.cache.insert(key, Arc::new(value));
+            generation
+        };
+        if let Some(generation) = generation {
+            generation.finish();
+        }
+        true
+    }
+
+    ///
+    /// Replaces the marker `waited_for` left by `SyncTimedCache::mark_pending` with a generation
+    /// run by the current thread, returning the new generation, if the marker is still in place.
+    ///
+    /// Threads waiting on the marker are woken, to wait for the new generation instead.
+    ///
+    fn replace_pending(&self, key: &Key, waited_for: &Arc<Generation>) -> Option<Arc<Generation>> {
+        let replacement = {
+            let mut state = self.lock_state(key);
+            let still_marked = state
+                .in_flight
+                .get(key)
+                .map_or(false, |generation| Arc::ptr_eq(generation, waited_for));
+            if !still_marked {
+                return None;
+            }
+            let replacement = Arc::new(Generation::new());
+            let _ = state.in_flight.insert(key.clone(), replacement.clone());
+            replacement
+        };
+        waited_for.finish();
+        Some(replacement)
+    }
+
+    ///
+    /// Returns how many generators are running at this moment, across all keys, such as to spot
+    /// a saturated backend.
+    ///
+    /// A thread waiting for a permit under `SyncTimedCache::set_generation_concurrency_limit`,
+    /// or waiting for another thread's generation, is not counted.
+    ///
+    pub fn in_flight_generations(&self) -> usize {
+        self.running_generations.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// Returns a copy of every value in the cache which is still considered valid, taken while
+    /// holding the lock on every shard at once, so no write is ever half seen.
+    ///
+    /// Every other thread using the cache waits while the copy is taken, so this suits occasional
+    /// diagnostic dumps rather than frequent use.
+    ///
+    pub fn snapshot(&self) -> HashMap<Key, Value>
+    where
+        Value: Clone,
+    {
+        // Shards are only ever locked all together here, and always in the same order, so
+        // this cannot deadlock with another snapshot.
+        let states: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        states
+            .iter()
+            .flat_map(|state| state.cache.iter())
+            .map(|(key, value)| (key.clone(), (**value).clone()))
+            .collect()
+    }
+
+    fn generate(
+        &self,
+        key: &Key,
+        generation: Arc<Generation>,
+        generate_value: impl Fn() -> Value,
+    ) -> Arc<Value> {
+        let _in_flight = InFlight {
+            cache: self,
+            key,
+            generation,
+        };
+        let value = Arc::new(self.generate_value(generate_value));
+        let _ = self
+            .lock_state(key)
+            .cache
+            .insert(key.clone(), value.clone());
+        value
+    }
+
+    ///
+    /// Calls `generate_value`, holding a permit while it runs if generations are limited.
+    ///
+    fn generate_value(&self, generate_value: impl Fn() -> Value) -> Value {
+        let _permit = self.generation_permits.as_ref().map(Semaphore::acquire);
+        let _running = Running::start(&self.running_generations);
+        generate_value()
+    }
+
+    ///
+    /// Returns the index of the shard `key` is routed to.
+    ///
+    fn shard_index(&self, key: &Key) -> usize {
+        let mut hasher = self.hash_builder.build_hasher();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    ///
+    /// Locks the shard `key` is routed to.
+    ///
+    fn lock_state(&self, key: &Key) -> MutexGuard<'_, State<Key, Value>> {
+        // NOTE: The lock is never held while calling user code, so a poisoned lock cannot have
+        // left the cache in an inconsistent state.
+        self.shards[self.shard_index(key)]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GetOutcome, SyncTimedCache};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread::{self, sleep};
+    use std::time::{Duration, Instant};
+    use ReentrantGeneration;
+    use TimeoutError;
+
+    const KEY: &str = "test";
+
+    #[test]
+    fn should_generate_once_for_concurrent_misses() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let generations = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let generations = generations.clone();
+                thread::spawn(move || {
+                    cache.get(&KEY.to_owned(), || {
+                        sleep(Duration::from_millis(50));
+                        generations.fetch_add(1, Ordering::SeqCst)
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(generations.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|&value| value == 0));
+    }
+
+    #[test]
+    fn should_stop_waiting_for_slow_generation_after_timeout() {
+        let mut cache = SyncTimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_generation_timeout(Duration::from_millis(20));
+        let cache = Arc::new(cache);
+
+        let (started, generation_started) = channel();
+        let slow = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(&KEY.to_owned(), || {
+                    started.send(()).unwrap();
+                    sleep(Duration::from_millis(500));
+                    1
+                })
+            })
+        };
+        generation_started.recv().unwrap();
+
+        let start = Instant::now();
+        let value = cache.get(&KEY.to_owned(), || 2);
+        assert_eq!(value, 2);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        assert_eq!(slow.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn should_generate_again_if_other_generation_panics() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+
+        let panicking = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.get(&KEY.to_owned(), || panic!("generation failed")))
+        };
+        assert!(panicking.join().is_err());
+
+        assert_eq!(cache.get(&KEY.to_owned(), || 3), 3);
+    }
+
+    #[test]
+    fn should_share_same_generated_value_between_threads() {
+        let cache = Arc::new(SyncTimedCache::<String, Vec<usize>>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    cache.get_arc(&KEY.to_owned(), || {
+                        sleep(Duration::from_millis(20));
+                        vec![0; 1024]
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(values.iter().all(|value| Arc::ptr_eq(value, &values[0])));
+        assert!(Arc::ptr_eq(
+            &values[0],
+            &cache.get_arc(&KEY.to_owned(), Vec::new)
+        ));
+    }
+
+    #[test]
+    fn should_never_exceed_generation_concurrency_limit() {
+        let mut cache = SyncTimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_generation_concurrency_limit(2);
+        let cache = Arc::new(cache);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8)
+            .map(|key| {
+                let cache = cache.clone();
+                let running = running.clone();
+                let most_running = most_running.clone();
+                thread::spawn(move || {
+                    cache.get(&key, || {
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_running.fetch_max(now_running, Ordering::SeqCst);
+                        sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        key
+                    })
+                })
+            })
+            .collect();
+
+        for (key, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), key);
+        }
+        assert!(most_running.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn should_report_hit_generated_and_waited_outcomes() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+
+        let (started, generation_started) = channel();
+        let generating = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get_reporting(&KEY.to_owned(), || {
+                    started.send(()).unwrap();
+                    sleep(Duration::from_millis(50));
+                    1
+                })
+            })
+        };
+        generation_started.recv().unwrap();
+
+        let (value, outcome) = cache.get_reporting(&KEY.to_owned(), || 2);
+        assert_eq!((*value, outcome), (1, GetOutcome::Waited));
+
+        let (value, outcome) = generating.join().unwrap();
+        assert_eq!((*value, outcome), (1, GetOutcome::Generated));
+
+        let (value, outcome) = cache.get_reporting(&KEY.to_owned(), || 3);
+        assert_eq!((*value, outcome), (1, GetOutcome::Hit));
+    }
+
+    #[test]
+    fn should_time_out_waiting_for_slow_generation() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+
+        let (started, generation_started) = channel();
+        let slow = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(&KEY.to_owned(), || {
+                    started.send(()).unwrap();
+                    sleep(Duration::from_millis(500));
+                    1
+                })
+            })
+        };
+        generation_started.recv().unwrap();
+
+        let start = Instant::now();
+        let result = cache.get_with_timeout(&KEY.to_owned(), || 2, Duration::from_millis(20));
+        assert_eq!(result, Err(TimeoutError));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        assert_eq!(slow.join().unwrap(), 1);
+        let result = cache.get_with_timeout(&KEY.to_owned(), || 2, Duration::from_millis(20));
+        assert_eq!(result, Ok(Arc::new(1)));
+    }
+
+    #[test]
+    fn should_wait_without_deadline_for_timeout_too_long_to_represent() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let (started_sender, started) = channel();
+
+        let generating = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(&KEY.to_owned(), || {
+                    started_sender.send(()).unwrap();
+                    sleep(Duration::from_millis(20));
+                    1
+                })
+            })
+        };
+        started.recv().unwrap();
+
+        let value = cache.get_with_timeout(&KEY.to_owned(), || unreachable!(), Duration::MAX);
+        assert_eq!(value.map(|value| *value), Ok(1));
+        assert_eq!(generating.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn should_not_block_keys_in_other_shards_while_a_shard_is_locked() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_shards(
+            8,
+            Duration::from_secs(10),
+        ));
+        let locked_key = KEY.to_owned();
+        let other_key = (0..)
+            .map(|i| format!("other-{}", i))
+            .find(|key| cache.shard_index(key) != cache.shard_index(&locked_key))
+            .unwrap();
+
+        let _locked_shard = cache.lock_state(&locked_key);
+        let (sender, receiver) = channel();
+        {
+            let cache = cache.clone();
+            let _ = thread::spawn(move || sender.send(cache.get(&other_key, || 1)).unwrap());
+        }
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn should_block_other_keys_behind_a_single_lock_unlike_shards() {
+        let single_lock = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let sharded = Arc::new(SyncTimedCache::<String, usize>::with_shards(
+            8,
+            Duration::from_secs(10),
+        ));
+        let locked_key = KEY.to_owned();
+        let other_key = (0..)
+            .map(|i| format!("other-{}", i))
+            .find(|key| sharded.shard_index(key) != sharded.shard_index(&locked_key))
+            .unwrap();
+
+        let get_while_locked = |cache: &Arc<SyncTimedCache<String, usize>>| {
+            let locked_shard = cache.lock_state(&locked_key);
+            let (sender, receiver) = channel();
+            {
+                let cache = cache.clone();
+                let other_key = other_key.clone();
+                let _ = thread::spawn(move || sender.send(cache.get(&other_key, || 1)).unwrap());
+            }
+            let while_locked = receiver.recv_timeout(Duration::from_millis(100));
+            drop(locked_shard);
+            (while_locked, receiver.recv_timeout(Duration::from_secs(5)))
+        };
+
+        let (while_locked, after_unlock) = get_while_locked(&single_lock);
+        assert_eq!(while_locked, Err(RecvTimeoutError::Timeout));
+        assert_eq!(after_unlock, Ok(1));
+
+        let (while_locked, _) = get_while_locked(&sharded);
+        assert_eq!(while_locked, Ok(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_without_shards() {
+        let _ = SyncTimedCache::<String, usize>::with_shards(0, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn should_report_generator_retrieving_its_own_key() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let inner = Mutex::new(None);
+
+        let value = cache.try_get_arc(&KEY.to_owned(), || {
+            *inner.lock().unwrap() = Some(cache.try_get_arc(&KEY.to_owned(), || 2));
+            1
+        });
+
+        assert_eq!(value, Ok(Arc::new(1)));
+        assert_eq!(*inner.lock().unwrap(), Some(Err(ReentrantGeneration)));
+    }
+
+    #[test]
+    #[should_panic(expected = "the generator tried to retrieve the value it is generating")]
+    fn should_panic_when_generator_gets_its_own_key() {
+        let cache = SyncTimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let _ = cache.get(&KEY.to_owned(), || cache.get(&KEY.to_owned(), || 2));
+    }
+
+    #[test]
+    fn should_snapshot_consistently_while_written_concurrently() {
+        let cache = Arc::new(SyncTimedCache::<usize, usize>::with_shards(
+            4,
+            Duration::from_secs(10),
+        ));
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        let key = writer * 1000 + i;
+                        cache.get(&key, || key);
+                    }
+                })
+            })
+            .collect();
+
+        let mut snapshots = Vec::new();
+        while snapshots.len() < 50 {
+            snapshots.push(cache.snapshot());
+        }
+        writers.into_iter().for_each(|t| t.join().unwrap());
+        snapshots.push(cache.snapshot());
+
+        for snapshot in &snapshots {
+            assert!(snapshot.iter().all(|(key, value)| key == value));
+            for writer in 0..4 {
+                // Each writer stores its keys in order, so a consistent snapshot holds a prefix.
+                let written = (0..500)
+                    .filter(|i| snapshot.contains_key(&(writer * 1000 + i)))
+                    .count();
+                assert!((0..written).all(|i| snapshot.contains_key(&(writer * 1000 + i))));
+            }
+        }
+        assert_eq!(snapshots.last().unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn should_wait_for_pending_key_to_be_fulfilled() {
+        let cache = Arc::new(SyncTimedCache::<String, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        assert!(cache.mark_pending(KEY.to_owned()));
+        assert!(!cache.mark_pending(KEY.to_owned()));
+        let generations = Arc::new(AtomicUsize::new(0));
+
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                let generations = generations.clone();
+                thread::spawn(move || {
+                    cache.get_reporting(&KEY.to_owned(), || {
+                        generations.fetch_add(1, Ordering::SeqCst)
+                    })
+                })
+            })
+            .collect();
+        sleep(Duration::from_millis(20));
+        assert!(cache.fulfill(KEY.to_owned(), 42));
+
+        for waiter in waiters {
+            let (value, outcome) = waiter.join().unwrap();
+            assert_eq!(*value, 42);
+            assert_ne!(outcome, GetOutcome::Generated);
+        }
+        assert_eq!(generations.load(Ordering::SeqCst), 0);
+        assert!(cache.mark_pending(KEY.to_owned()));
+    }
+
+    #[test]
+    fn should_not_fulfill_key_while_it_is_being_generated() {
+        let cache = Arc::new(SyncTimedCache::<usize, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let started = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+
+        let generating = {
+            let cache = cache.clone();
+            let started = started.clone();
+            let release = release.clone();
+            thread::spawn(move || {
+                cache.get(&1, || {
+                    started.wait();
+                    release.wait();
+                    1
+                })
+            })
+        };
+        started.wait();
+        assert!(!cache.fulfill(1, 42));
+        assert!(!cache.mark_pending(1));
+        release.wait();
+
+        assert_eq!(generating.join().unwrap(), 1);
+        assert!(cache.mark_pending(1));
+        assert!(cache.fulfill(1, 42));
+        assert_eq!(cache.get(&1, || unreachable!()), 42);
+    }
+
+    #[test]
+    fn should_take_over_pending_key_which_is_never_fulfilled() {
+        let mut cache = SyncTimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_generation_timeout(Duration::from_millis(20));
+        let cache = Arc::new(cache);
+        assert!(cache.mark_pending(KEY.to_owned()));
+
+        let waiter = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.get_reporting(&KEY.to_owned(), || 7))
+        };
+        let (value, outcome) = waiter.join().unwrap();
+
+        assert_eq!((*value, outcome), (7, GetOutcome::Generated));
+        assert!(cache.mark_pending(KEY.to_owned()));
+    }
+
+    #[test]
+    fn should_report_marking_thread_retrieving_its_pending_key() {
+        let cache = SyncTimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert!(cache.mark_pending(KEY.to_owned()));
+
+        assert_eq!(
+            cache.try_get_arc(&KEY.to_owned(), || 1).unwrap_err(),
+            ReentrantGeneration
+        );
+    }
+
+    #[test]
+    fn should_not_wait_for_own_generation_with_timeout() {
+        let cache = SyncTimedCache::<String, usize>::with_time_to_keep(Duration::from_secs(10));
+        let key = KEY.to_owned();
+        let started = Instant::now();
+
+        let value = cache.get_with_timeout(
+            &key,
+            || {
+                let inner = cache.get_with_timeout(&key, || 2, Duration::MAX);
+                assert_eq!(inner, Err(TimeoutError));
+                1
+            },
+            Duration::MAX,
+        );
+        assert_eq!(value.map(|value| *value), Ok(1));
+
+        assert!(cache.mark_pending("other".to_owned()));
+        assert_eq!(
+            cache.get_with_timeout(&"other".to_owned(), || 3, Duration::MAX),
+            Err(TimeoutError)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_count_generations_in_flight() {
+        let cache = Arc::new(SyncTimedCache::<usize, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+        ));
+        let started = Arc::new(Barrier::new(5));
+        let release = Arc::new(Barrier::new(5));
+        assert_eq!(cache.in_flight_generations(), 0);
+
+        let threads: Vec<_> = (0..4)
+            .map(|key| {
+                let cache = cache.clone();
+                let started = started.clone();
+                let release = release.clone();
+                thread::spawn(move || {
+                    cache.get(&key, || {
+                        started.wait();
+                        release.wait();
+                        key
+                    })
+                })
+            })
+            .collect();
+        started.wait();
+        assert_eq!(cache.in_flight_generations(), 4);
+        release.wait();
+
+        threads.into_iter().for_each(|t| {
+            t.join().unwrap();
+        });
+        assert_eq!(cache.in_flight_generations(), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -7,30 +8,109 @@ use std::time::Instant;
 pub(crate) struct TimedData<T> {
     pub(crate) item: T,
     pub(crate) time_stored: Instant,
+    ///
+    /// When this version of the value was generated or stored. Unlike `time_stored`, this is not
+    /// reset when a hit slides the window of validity forward.
+    ///
+    pub(crate) time_generated: Instant,
+    ///
+    /// The time to live for this data in particular, overriding the default time to live it is
+    /// checked against, if set.
+    ///
+    pub(crate) time_to_live: Option<Duration>,
+    ///
+    /// Whether `time_to_live` was grown from the default by the owning cache's adaptive time to
+    /// live, rather than given to this data in particular.
+    ///
+    pub(crate) ttl_adapted: bool,
+    ///
+    /// When this data was last stored or retrieved, as a tick of the owning cache's use counter.
+    ///
+    pub(crate) last_used: u64,
+    ///
+    /// Identifies this version of the value, as the tick of the owning cache's use counter when
+    /// it was stored.
+    ///
+    pub(crate) generation_id: u64,
+    ///
+    /// Arbitrary data attached to this version of the value by the user, if any.
+    ///
+    pub(crate) metadata: Option<Box<dyn Any + Send + Sync>>,
 }
 
 impl<T> TimedData<T> {
     pub(crate) fn new(item: T) -> TimedData<T> {
+        TimedData::new_at(item, Instant::now())
+    }
+
+    ///
+    /// Creates data recorded as stored at the instant `time_stored`, rather than the current
+    /// instant.
+    ///
+    pub(crate) fn new_at(item: T, time_stored: Instant) -> TimedData<T> {
         TimedData {
             item,
-            time_stored: Instant::now(),
+            time_stored,
+            time_generated: time_stored,
+            time_to_live: None,
+            ttl_adapted: false,
+            last_used: 0,
+            generation_id: 0,
+            metadata: None,
         }
     }
 
-    pub(crate) fn still_valid(&self, time_to_live: Duration) -> bool {
+    pub(crate) fn still_valid(&self, default_time_to_live: Duration) -> bool {
+        self.still_valid_at(default_time_to_live, Instant::now())
+    }
+
+    ///
+    /// Checks whether the data is still valid at the instant `now`, rather than the current
+    /// instant.
+    ///
+    /// A `now` before the data was stored, as given by a clock which went backwards, is treated
+    /// as the instant it was stored rather than underflowing.
+    ///
+    pub(crate) fn still_valid_at(&self, default_time_to_live: Duration, now: Instant) -> bool {
         // NOTE(zac):
         // A token is still valid if it has not been alive for longer than the
         // specified time_to_live.
-        let time_lived_thus_far = Instant::now() - self.time_stored;
+        let time_to_live = self.time_to_live.unwrap_or(default_time_to_live);
+        let time_lived_thus_far = now.saturating_duration_since(self.time_stored);
         time_to_live > time_lived_thus_far
     }
+
+    ///
+    /// Returns the instant the data stops being valid, or `None` if that instant is too far in
+    /// the future to be represented.
+    ///
+    pub(crate) fn expires_at(&self, default_time_to_live: Duration) -> Option<Instant> {
+        let time_to_live = self.time_to_live.unwrap_or(default_time_to_live);
+        self.time_stored.checked_add(time_to_live)
+    }
+
+    ///
+    /// Returns how much longer the data will be valid for at the instant `now`, or `None` if it
+    /// is no longer valid.
+    ///
+    pub(crate) fn remaining_at(
+        &self,
+        default_time_to_live: Duration,
+        now: Instant,
+    ) -> Option<Duration> {
+        let time_to_live = self.time_to_live.unwrap_or(default_time_to_live);
+        let time_lived_thus_far = now.saturating_duration_since(self.time_stored);
+        time_to_live
+            .checked_sub(time_lived_thus_far)
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
     use std::thread::sleep;
+    use std::time::Duration;
 
     #[test]
     fn should_be_considered_valid_if_within_duration() {
@@ -39,7 +119,6 @@ mod tests {
         assert!(timed_data.still_valid(time_to_live));
     }
 
-
     #[test]
     fn should_not_be_considered_valid_if_after_duration() {
         let time_to_live = Duration::from_millis(5);
@@ -47,4 +126,85 @@ mod tests {
         sleep(time_to_live);
         assert!(!timed_data.still_valid(time_to_live));
     }
+
+    #[test]
+    fn should_be_considered_valid_at_instants_before_duration_elapses() {
+        let time_to_live = Duration::from_secs(10);
+        let timed_data = TimedData::new(5);
+        let stored = timed_data.time_stored;
+
+        assert!(timed_data.still_valid_at(time_to_live, stored));
+        assert!(timed_data.still_valid_at(
+            time_to_live,
+            stored + time_to_live - Duration::from_nanos(1)
+        ));
+    }
+
+    #[test]
+    fn should_not_be_considered_valid_at_instants_after_duration_elapses() {
+        let time_to_live = Duration::from_secs(10);
+        let timed_data = TimedData::new(5);
+        let stored = timed_data.time_stored;
+
+        assert!(!timed_data.still_valid_at(time_to_live, stored + time_to_live));
+        assert!(!timed_data.still_valid_at(
+            time_to_live,
+            stored + time_to_live + Duration::from_nanos(1)
+        ));
+    }
+
+    #[test]
+    fn should_be_considered_fresh_at_instants_before_being_stored() {
+        let time_to_live = Duration::from_secs(10);
+        let timed_data = TimedData::new_at(5, Instant::now() + Duration::from_secs(60));
+        let earlier = timed_data.time_stored - Duration::from_secs(60);
+
+        assert!(timed_data.still_valid_at(time_to_live, earlier));
+        assert_eq!(
+            timed_data.remaining_at(time_to_live, earlier),
+            Some(time_to_live)
+        );
+    }
+
+    #[test]
+    fn should_prefer_own_time_to_live_over_default() {
+        let mut timed_data = TimedData::new(5);
+        timed_data.time_to_live = Some(Duration::from_secs(1));
+        let stored = timed_data.time_stored;
+
+        assert!(
+            !timed_data.still_valid_at(Duration::from_secs(10), stored + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn should_have_time_remaining_until_duration_elapses() {
+        let time_to_live = Duration::from_secs(10);
+        let timed_data = TimedData::new(5);
+        let stored = timed_data.time_stored;
+
+        assert_eq!(
+            timed_data.remaining_at(time_to_live, stored),
+            Some(time_to_live)
+        );
+        assert_eq!(
+            timed_data.remaining_at(time_to_live, stored + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            timed_data.remaining_at(time_to_live, stored + time_to_live),
+            None
+        );
+    }
+
+    #[test]
+    fn should_expire_after_time_to_live() {
+        let time_to_live = Duration::from_secs(10);
+        let timed_data = TimedData::new(5);
+
+        assert_eq!(
+            timed_data.expires_at(time_to_live),
+            Some(timed_data.time_stored + time_to_live)
+        );
+    }
 }
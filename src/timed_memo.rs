@@ -0,0 +1,116 @@
+use default_ttl;
+use std::time::Duration;
+use timed_data::TimedData;
+
+///
+/// A single value which is stored for a set amount of time, like a `TimedCache` with only one
+/// key.
+///
+/// This suits caching exactly one value, such as a session token, without needing a dummy key,
+/// and without the cost of hashing it on every retrieval.
+///
+pub struct TimedMemo<Value> {
+    pub(crate) time_to_keep: Duration,
+    data: Option<TimedData<Value>>,
+}
+
+impl<Value> TimedMemo<Value> {
+    ///
+    /// Creates an empty `TimedMemo` with the specified `Duration` as the length of time the value
+    /// will be considered 'valid' after being stored.
+    ///
+    pub fn with_time_to_keep(time_to_keep: Duration) -> TimedMemo<Value> {
+        TimedMemo {
+            time_to_keep,
+            data: None,
+        }
+    }
+
+    ///
+    /// Retrieves a reference to the stored value if there is one and it is still considered
+    /// valid, otherwise calls `generate_value` to generate the value to store and returns a
+    /// reference to that value, like `TimedCache::get`.
+    ///
+    pub fn get(&mut self, generate_value: impl Fn() -> Value) -> &Value {
+        if self.get_if_present().is_none() {
+            let value = generate_value();
+            self.data = Some(TimedData::new(value));
+        }
+        &self.data.as_ref().expect("value should be stored").item
+    }
+
+    ///
+    /// Retrieves a reference to the stored value only if there is one and it is still considered
+    /// valid.
+    ///
+    pub fn get_if_present(&self) -> Option<&Value> {
+        self.data
+            .as_ref()
+            .filter(|data| data.still_valid(self.time_to_keep))
+            .map(|data| &data.item)
+    }
+
+    ///
+    /// Removes the stored value, returning it whether or not it was still valid.
+    ///
+    pub fn clear(&mut self) -> Option<Value> {
+        self.data.take().map(|data| data.item)
+    }
+}
+
+///
+/// Creates an empty memo with the time to keep set with `set_global_default_ttl`, or 60 seconds
+/// if none has been set.
+///
+impl<Value> Default for TimedMemo<Value> {
+    fn default() -> TimedMemo<Value> {
+        TimedMemo::with_time_to_keep(default_ttl::global_default_ttl())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimedMemo;
+    use std::cell::Cell;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn should_keep_same_value_within_duration() {
+        let generated = Cell::new(0);
+        let generate_value = || {
+            generated.set(generated.get() + 1);
+            generated.get()
+        };
+        let mut memo = TimedMemo::with_time_to_keep(Duration::from_secs(10));
+
+        assert_eq!(*memo.get(generate_value), 1);
+        assert_eq!(*memo.get(generate_value), 1);
+        assert_eq!(memo.get_if_present(), Some(&1));
+    }
+
+    #[test]
+    fn should_regenerate_value_after_duration() {
+        let generated = Cell::new(0);
+        let generate_value = || {
+            generated.set(generated.get() + 1);
+            generated.get()
+        };
+        let mut memo = TimedMemo::with_time_to_keep(Duration::from_millis(5));
+
+        assert_eq!(*memo.get(generate_value), 1);
+        sleep(Duration::from_millis(5));
+        assert_eq!(memo.get_if_present(), None);
+        assert_eq!(*memo.get(generate_value), 2);
+    }
+
+    #[test]
+    fn should_regenerate_value_after_clear() {
+        let mut memo = TimedMemo::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(memo.get_if_present(), None);
+        memo.get(|| 1);
+
+        assert_eq!(memo.clear(), Some(1));
+        assert_eq!(*memo.get(|| 2), 2);
+    }
+}
@@ -0,0 +1,119 @@
+use std::hash::Hash;
+use std::time::Duration;
+use TimedCache;
+
+///
+/// The proportion of keys which must have expired for inserting a key to purge the expired keys.
+///
+const AUTO_PURGE_THRESHOLD: f32 = 0.5;
+
+///
+/// A set which only remembers each key for a set amount of time, such as to deduplicate events
+/// within a time window.
+///
+/// This is a `TimedCache` storing no value for each key, so everything about when keys expire
+/// works the same way. As a set used to deduplicate is often inserted into forever, expired keys
+/// are purged automatically while inserting, as with `TimedCache::set_auto_purge`, once more than
+/// half of the keys stored have expired.
+///
+pub struct TimedSet<Key: Hash + Eq> {
+    cache: TimedCache<Key, ()>,
+}
+
+impl<Key: Hash + Eq> TimedSet<Key> {
+    ///
+    /// Creates a `TimedSet` with the specified `Duration` as the length of time keys will be
+    /// considered present after they are inserted.
+    ///
+    pub fn with_time_to_keep(time_to_keep: Duration) -> TimedSet<Key> {
+        let mut cache = TimedCache::with_time_to_keep(time_to_keep);
+        cache.set_auto_purge(AUTO_PURGE_THRESHOLD);
+        TimedSet { cache }
+    }
+
+    ///
+    /// Inserts the `key`, resetting the time it was inserted if it is already present, and
+    /// returns `true` if it was not already present.
+    ///
+    pub fn insert(&mut self, key: Key) -> bool {
+        let already_present = self.contains(&key);
+        let _ = self.cache.insert(key, ());
+        !already_present
+    }
+
+    ///
+    /// Checks whether the `key` was inserted recently enough to still be considered present.
+    ///
+    pub fn contains(&self, key: &Key) -> bool {
+        self.cache.get_if_present(key).is_some()
+    }
+
+    ///
+    /// Returns the number of keys stored, including any which are no longer considered present
+    /// but have not yet been purged, like `TimedCache::len`.
+    ///
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    ///
+    /// Returns `true` if no keys are stored.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    ///
+    /// Removes every key which is no longer considered present, returning how many were removed.
+    ///
+    pub fn purge_expired(&mut self) -> usize {
+        self.cache.purge_expired()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimedSet;
+    use std::thread::sleep;
+    use std::time::Duration;
+    use ManualClock;
+
+    const KEY: &str = "test";
+
+    #[test]
+    fn should_contain_key_only_until_time_to_keep_elapses() {
+        let time_to_keep = Duration::from_millis(10);
+        let mut set = TimedSet::with_time_to_keep(time_to_keep);
+        assert!(!set.contains(&KEY));
+
+        assert!(set.insert(KEY));
+        assert!(!set.insert(KEY));
+        assert!(set.contains(&KEY));
+
+        sleep(time_to_keep);
+        assert!(!set.contains(&KEY));
+        assert!(set.insert(KEY));
+    }
+
+    #[test]
+    fn should_purge_expired_keys_while_inserting() {
+        let mut set = TimedSet::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        set.cache.set_clock(clock.clone());
+
+        for key in 0..100 {
+            set.insert(key);
+        }
+        clock.advance(Duration::from_secs(10));
+        for key in 100..200 {
+            set.insert(key);
+        }
+
+        assert!(set.len() < 200);
+        assert!((100..200).all(|key| set.contains(&key)));
+        clock.advance(Duration::from_secs(10));
+        let stored = set.len();
+        assert_eq!(set.purge_expired(), stored);
+        assert!(set.is_empty());
+    }
+}
@@ -0,0 +1,17 @@
+use std::error::Error;
+use std::fmt;
+
+///
+/// The error returned when a value could not be retrieved from the cache in time, because
+/// another thread was still generating it.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError;
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out waiting for the value to be generated")
+    }
+}
+
+impl Error for TimeoutError {}
@@ -0,0 +1,104 @@
+use std::hash::Hash;
+use std::time::Duration;
+use TimedCache;
+
+///
+/// A boxed function transforming a value into the form it is stored in.
+///
+type OnStore<Value, Stored> = Box<dyn Fn(Value) -> Stored + Send>;
+
+///
+/// A boxed function recovering a value from the form it is stored in.
+///
+type OnLoad<Stored, Value> = Box<dyn Fn(&Stored) -> Value + Send>;
+
+///
+/// A `TimedCache` which stores each value in a transformed form, such as compressed or
+/// encrypted, and transforms it back whenever it is retrieved.
+///
+/// Every value is passed through `on_store` before it is stored, and every stored value through
+/// `on_load` as it is retrieved, so callers only ever see the original values. As the original
+/// value is rebuilt on each retrieval, values are returned owned rather than by reference.
+///
+pub struct TransformedTimedCache<Key: Hash + Eq + Clone, Value, Stored> {
+    cache: TimedCache<Key, Stored>,
+    on_store: OnStore<Value, Stored>,
+    on_load: OnLoad<Stored, Value>,
+}
+
+impl<Key: Hash + Eq + Clone, Value, Stored> TransformedTimedCache<Key, Value, Stored> {
+    ///
+    /// Creates a `TransformedTimedCache` with the specified `Duration` as the length of time the
+    /// values will be considered 'valid' after initial storage, which stores values as
+    /// transformed by `on_store` and recovers them with `on_load`.
+    ///
+    /// `on_load` should undo `on_store`, so that a value retrieved is equal to the value stored.
+    ///
+    pub fn with_time_to_keep(
+        time_to_keep: Duration,
+        on_store: impl Fn(Value) -> Stored + Send + 'static,
+        on_load: impl Fn(&Stored) -> Value + Send + 'static,
+    ) -> TransformedTimedCache<Key, Value, Stored> {
+        TransformedTimedCache {
+            cache: TimedCache::with_time_to_keep(time_to_keep),
+            on_store: Box::new(on_store),
+            on_load: Box::new(on_load),
+        }
+    }
+
+    ///
+    /// Retrieves the value stored in the cache for the `key` if it exists and is still
+    /// considered valid, otherwise generates and stores the value, like `TimedCache::get`.
+    ///
+    pub fn get(&mut self, key: &Key, generate_value: impl Fn() -> Value) -> Value {
+        let on_store = &self.on_store;
+        let stored = self.cache.get(key, || on_store(generate_value()));
+        (self.on_load)(stored)
+    }
+
+    ///
+    /// Retrieves the value stored in the cache for the `key` only if it exists and is still
+    /// considered valid.
+    ///
+    pub fn get_if_present(&self, key: &Key) -> Option<Value> {
+        self.cache
+            .get_if_present(key)
+            .map(|stored| (self.on_load)(stored))
+    }
+
+    ///
+    /// Stores the `value` in the cache for the `key`, like `TimedCache::insert`, returning the
+    /// value previously stored, if any.
+    ///
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        let stored = (self.on_store)(value);
+        self.cache
+            .insert(key, stored)
+            .map(|previous| (self.on_load)(&previous))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransformedTimedCache;
+    use std::time::Duration;
+
+    #[test]
+    fn should_return_original_values_while_storing_transformed_ones() {
+        let mut cache = TransformedTimedCache::<usize, usize, usize>::with_time_to_keep(
+            Duration::from_secs(10),
+            |value| value + 1,
+            |stored| stored - 1,
+        );
+
+        assert_eq!(cache.get(&1, || 10), 10);
+        assert_eq!(cache.cache.get_if_present(&1), Some(&11));
+        assert_eq!(cache.get(&1, || unreachable!()), 10);
+        assert_eq!(cache.get_if_present(&1), Some(10));
+
+        assert_eq!(cache.insert(1, 20), Some(10));
+        assert_eq!(cache.cache.get_if_present(&1), Some(&21));
+        assert_eq!(cache.get_if_present(&1), Some(20));
+        assert_eq!(cache.get_if_present(&2), None);
+    }
+}
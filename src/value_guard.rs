@@ -0,0 +1,85 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Deref;
+use TimedCache;
+
+///
+/// A guard giving access to a value in a `TimedCache`, returned by `TimedCache::get_guard`.
+///
+/// The guard dereferences to the value. Calling `ValueGuard::commit` resets the time the value
+/// was stored, refreshing it; dropping the guard without committing leaves that time untouched,
+/// so the value only stays fresh if the work done with it succeeded.
+///
+pub struct ValueGuard<'a, Key: Hash + Eq, Value, S: BuildHasher = RandomState> {
+    cache: &'a mut TimedCache<Key, Value, S>,
+    key: Key,
+}
+
+impl<'a, Key: Hash + Eq, Value, S: BuildHasher> ValueGuard<'a, Key, Value, S> {
+    pub(crate) fn new(
+        cache: &'a mut TimedCache<Key, Value, S>,
+        key: Key,
+    ) -> ValueGuard<'a, Key, Value, S> {
+        ValueGuard { cache, key }
+    }
+
+    ///
+    /// Resets the time the value was stored to now, so that it is considered valid for another
+    /// full time to live.
+    ///
+    pub fn commit(self) {
+        let now = self.cache.clock.now();
+        if let Some(timed_data) = self.cache.store.get_mut(&self.key) {
+            timed_data.time_stored = now;
+        }
+    }
+}
+
+impl<'a, Key: Hash + Eq, Value, S: BuildHasher> Deref for ValueGuard<'a, Key, Value, S> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.cache.store[&self.key].item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use ManualClock;
+    use TimedCache;
+
+    #[test]
+    fn should_refresh_value_only_when_committed() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        let clock = ManualClock::new();
+        cache.set_clock(clock.clone());
+        cache.get(&1, || 10);
+        clock.advance(Duration::from_secs(6));
+
+        {
+            let guard = cache.get_guard(&1, || unreachable!()).unwrap();
+            assert_eq!(*guard, 10);
+        }
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(6)));
+
+        let guard = cache.get_guard(&1, || unreachable!()).unwrap();
+        assert_eq!(*guard, 10);
+        guard.commit();
+        assert_eq!(cache.age(&1), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn should_generate_value_for_guard_on_miss() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        assert_eq!(*cache.get_guard(&1, || 10).unwrap(), 10);
+        assert_eq!(cache.get_if_present(&1), Some(&10));
+    }
+
+    #[test]
+    fn should_not_guard_uncacheable_key() {
+        let mut cache = TimedCache::<usize, usize>::with_time_to_keep(Duration::from_secs(10));
+        cache.set_uncacheable(|key| *key == 1);
+        assert!(cache.get_guard(&1, || 10).is_none());
+    }
+}